@@ -14,24 +14,40 @@ pub enum ArchiveFormat {
 }
 
 impl ArchiveFormat {
-    pub fn detect(path: &Path) -> Result<Self> {
-        let mut file = fs::File::open(path)?;
-        let mut magic = [0u8; 8];
-        let n = file.read(&mut magic)?;
-        let magic = &magic[..n];
-
+    /// Detect the archive format from the first bytes of a file.
+    pub fn from_magic(magic: &[u8]) -> Option<Self> {
         if magic.starts_with(b"PK") {
-            return Ok(Self::Zip);
+            return Some(Self::Zip);
         }
         if magic.starts_with(b"Rar!") {
-            return Ok(Self::Rar);
+            return Some(Self::Rar);
         }
         if magic.starts_with(b"7z\xBC\xAF\x27\x1C") {
-            return Ok(Self::SevenZ);
+            return Some(Self::SevenZ);
         }
         // LZH: bytes 2-4 are "-lh" or "-lz"
         if magic.len() >= 5 && (magic[2] == b'-') && (magic[3] == b'l') {
-            return Ok(Self::Lzh);
+            return Some(Self::Lzh);
+        }
+
+        None
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Zip => "zip",
+            Self::Rar => "rar",
+            Self::SevenZ => "7z",
+            Self::Lzh => "lzh",
+        }
+    }
+
+    pub fn detect(path: &Path) -> Result<Self> {
+        let mut file = fs::File::open(path)?;
+        let mut magic = [0u8; 8];
+        let n = file.read(&mut magic)?;
+        if let Some(format) = Self::from_magic(&magic[..n]) {
+            return Ok(format);
         }
 
         // Fallback to extension
@@ -183,7 +199,12 @@ pub fn is_html(path: &Path) -> bool {
     let Ok(n) = file.read(&mut buf) else {
         return false;
     };
-    let content = String::from_utf8_lossy(&buf[..n]).to_lowercase();
+    is_html_bytes(&buf[..n])
+}
+
+/// Check if the first bytes of a file appear to be HTML content.
+pub fn is_html_bytes(head: &[u8]) -> bool {
+    let content = String::from_utf8_lossy(head).to_lowercase();
     content.contains("<!doctype html") || content.contains("<html")
 }
 
@@ -0,0 +1,297 @@
+use std::path::Path;
+
+use anyhow::Result;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResourceKind {
+    /// Keysound or BGM (`#WAVxx`, bmson sound channels)
+    Sound,
+    /// BGA image or video (`#BMPxx`, bmson BGA headers)
+    Image,
+}
+
+/// A file referenced by a chart, relative to the chart's folder with `/` separators
+#[derive(Debug, Clone)]
+pub struct Resource {
+    pub kind: ResourceKind,
+    pub path: String,
+}
+
+/// Header metadata and resources of a BMS or bmson chart.
+///
+/// Headers at the top level win over those inside `#IF` and `#SWITCH` blocks, which only
+/// fill in missing ones; resources of all branches are collected.
+#[derive(Debug, Clone, Default)]
+pub struct Chart {
+    pub title: Option<String>,
+    pub subtitle: Option<String>,
+    pub artist: Option<String>,
+    pub genre: Option<String>,
+    pub playlevel: Option<String>,
+    /// 1 (beginner) to 5 (insane)
+    pub difficulty: Option<u32>,
+    pub total: Option<f64>,
+    /// Judge rank: 0 (very hard) to 3 (easy) for BMS, percentage for bmson
+    pub rank: Option<u32>,
+    pub bpm: Option<f64>,
+    pub resources: Vec<Resource>,
+    /// The chart contains `#RANDOM` blocks
+    pub random: bool,
+}
+
+impl Chart {
+    /// Fill in headers missing from `self` with those of `other` and add its resources.
+    fn merge(mut self, other: Chart) -> Chart {
+        self.title = self.title.or(other.title);
+        self.subtitle = self.subtitle.or(other.subtitle);
+        self.artist = self.artist.or(other.artist);
+        self.genre = self.genre.or(other.genre);
+        self.playlevel = self.playlevel.or(other.playlevel);
+        self.difficulty = self.difficulty.or(other.difficulty);
+        self.total = self.total.or(other.total);
+        self.rank = self.rank.or(other.rank);
+        self.bpm = self.bpm.or(other.bpm);
+        self.resources.extend(other.resources);
+        self.random |= other.random;
+        self
+    }
+}
+
+/// Parse a chart file, detecting bmson by extension and the text encoding by content.
+pub fn parse(path: &Path) -> Result<Chart> {
+    let bytes = std::fs::read(path)?;
+    let text = decode(&bytes);
+
+    let is_bmson = path
+        .extension()
+        .is_some_and(|e| e.eq_ignore_ascii_case("bmson"));
+    if is_bmson {
+        parse_bmson(&text)
+    } else {
+        Ok(parse_bms(&text))
+    }
+}
+
+/// Decode a chart as UTF-8, falling back to Shift_JIS used by most BMS files.
+fn decode(bytes: &[u8]) -> String {
+    let bytes = bytes.strip_prefix(b"\xEF\xBB\xBF").unwrap_or(bytes);
+    match std::str::from_utf8(bytes) {
+        Ok(text) => text.to_string(),
+        Err(_) => encoding_rs::SHIFT_JIS.decode(bytes).0.into_owned(),
+    }
+}
+
+pub fn parse_bms(text: &str) -> Chart {
+    let mut chart = Chart::default();
+    let mut branches = Chart::default();
+    // Nesting of #IF and #SWITCH blocks
+    let mut depth = 0usize;
+
+    for line in text.lines() {
+        let Some(rest) = line.trim().strip_prefix('#') else {
+            continue;
+        };
+        let (command, value) = match rest.split_once(char::is_whitespace) {
+            Some((command, value)) => (command, value.trim()),
+            None => (rest, ""),
+        };
+        let command = command.to_ascii_uppercase();
+        let target = if depth == 0 {
+            &mut chart
+        } else {
+            &mut branches
+        };
+
+        match command.as_str() {
+            "IF" | "SWITCH" => depth += 1,
+            "ENDIF" | "ENDSW" => depth = depth.saturating_sub(1),
+            "RANDOM" | "SETRANDOM" => target.random = true,
+            "TITLE" => set(&mut target.title, value),
+            "SUBTITLE" => set(&mut target.subtitle, value),
+            "ARTIST" => set(&mut target.artist, value),
+            "GENRE" => set(&mut target.genre, value),
+            "PLAYLEVEL" => set(&mut target.playlevel, value),
+            "DIFFICULTY" => target.difficulty = target.difficulty.or(value.parse().ok()),
+            "TOTAL" => target.total = target.total.or(value.parse().ok()),
+            "RANK" => target.rank = target.rank.or(value.parse().ok()),
+            "BPM" => target.bpm = target.bpm.or(value.parse().ok()),
+            // #WAVxx name / #BMPxx name
+            c if c.len() == 5
+                && (c.starts_with("WAV") || c.starts_with("BMP"))
+                && !value.is_empty() =>
+            {
+                let kind = if c.starts_with("WAV") {
+                    ResourceKind::Sound
+                } else {
+                    ResourceKind::Image
+                };
+                target.resources.push(Resource {
+                    kind,
+                    path: value.replace('\\', "/"),
+                });
+            }
+            _ => {}
+        }
+    }
+
+    chart.merge(branches)
+}
+
+pub fn parse_bmson(text: &str) -> Result<Chart> {
+    let json: serde_json::Value = serde_json::from_str(text)?;
+    let info = &json["info"];
+    let string =
+        |value: &serde_json::Value| value.as_str().filter(|s| !s.is_empty()).map(String::from);
+    let resources = |list: &serde_json::Value, kind: ResourceKind| {
+        list.as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(|item| item["name"].as_str())
+            .map(move |name| Resource {
+                kind,
+                path: name.replace('\\', "/"),
+            })
+            .collect::<Vec<_>>()
+    };
+
+    let mut chart = Chart {
+        title: string(&info["title"]),
+        subtitle: string(&info["subtitle"]),
+        artist: string(&info["artist"]),
+        genre: string(&info["genre"]),
+        playlevel: info["level"].as_u64().map(|l| l.to_string()),
+        difficulty: None,
+        total: info["total"].as_f64(),
+        rank: info["judge_rank"].as_u64().map(|r| r as u32),
+        bpm: info["init_bpm"].as_f64(),
+        resources: resources(&json["sound_channels"], ResourceKind::Sound),
+        random: false,
+    };
+    chart
+        .resources
+        .extend(resources(&json["bga"]["bga_header"], ResourceKind::Image));
+
+    Ok(chart)
+}
+
+fn set(field: &mut Option<String>, value: &str) {
+    if field.is_none() && !value.is_empty() {
+        *field = Some(value.to_string());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn paths(chart: &Chart, kind: ResourceKind) -> Vec<&str> {
+        chart
+            .resources
+            .iter()
+            .filter(|r| r.kind == kind)
+            .map(|r| r.path.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn decodes_shift_jis() {
+        let text = "#TITLE 夜明けの歌\r\n#ARTIST ＢＭＳ作者\r\n#WAV01 音\\ド.wav\r\n";
+        let (bytes, _, _) = encoding_rs::SHIFT_JIS.encode(text);
+        assert!(std::str::from_utf8(&bytes).is_err());
+
+        let chart = parse_bms(&decode(&bytes));
+        assert_eq!(chart.title.as_deref(), Some("夜明けの歌"));
+        assert_eq!(chart.artist.as_deref(), Some("ＢＭＳ作者"));
+        assert_eq!(paths(&chart, ResourceKind::Sound), ["音/ド.wav"]);
+
+        let utf8 = decode("\u{FEFF}#TITLE Song".as_bytes());
+        assert_eq!(parse_bms(&utf8).title.as_deref(), Some("Song"));
+    }
+
+    #[test]
+    fn top_level_headers_win_over_random_branches() {
+        let text = "\
+#RANDOM 2
+#IF 1
+#TITLE Branch One
+#ARTIST Branch Artist
+#WAV01 one.wav
+#ENDIF
+#IF 2
+#TITLE Branch Two
+#BMP01 two.bmp
+#ENDIF
+#ENDRANDOM
+#TITLE Song
+#WAV02 bgm.ogg
+";
+        let chart = parse_bms(text);
+        assert_eq!(chart.title.as_deref(), Some("Song"));
+        assert_eq!(chart.artist.as_deref(), Some("Branch Artist"));
+        assert_eq!(paths(&chart, ResourceKind::Sound), ["bgm.ogg", "one.wav"]);
+        assert_eq!(paths(&chart, ResourceKind::Image), ["two.bmp"]);
+    }
+
+    #[test]
+    fn parses_numeric_headers() {
+        let text = "\
+#GENRE Techno
+#SUBTITLE [ANOTHER]
+#PLAYLEVEL 12
+#DIFFICULTY 4
+#TOTAL 320.5
+#RANK 2
+#RANDOM 2
+#IF 1
+#BPM 200
+#RANK 0
+#ENDIF
+#ENDRANDOM
+#BPM 150.25
+#TOTAL not a number
+";
+        let chart = parse_bms(text);
+        assert_eq!(chart.genre.as_deref(), Some("Techno"));
+        assert_eq!(chart.subtitle.as_deref(), Some("[ANOTHER]"));
+        assert_eq!(chart.playlevel.as_deref(), Some("12"));
+        assert_eq!(chart.difficulty, Some(4));
+        assert_eq!(chart.total, Some(320.5));
+        assert_eq!(chart.rank, Some(2));
+        assert_eq!(chart.bpm, Some(150.25));
+        assert!(chart.random);
+
+        let chart = parse_bms("#TOTAL abc\n#DIFFICULTY\n#IF 1\n#BPM 180\n#ENDIF\n");
+        assert_eq!(chart.total, None);
+        assert_eq!(chart.difficulty, None);
+        assert_eq!(chart.bpm, Some(180.0));
+        assert!(!chart.random);
+    }
+
+    #[test]
+    fn parses_bmson() {
+        let text = r#"{
+            "info": {
+                "title": "Song", "subtitle": "[HYPER]", "artist": "", "genre": "Trance",
+                "level": 12, "total": 400.0, "judge_rank": 100, "init_bpm": 174.5
+            },
+            "sound_channels": [{ "name": "kick.wav", "notes": [] }, { "name": "sub\\snare.ogg" }],
+            "bga": { "bga_header": [{ "id": 1, "name": "bga.mp4" }] }
+        }"#;
+        let chart = parse_bmson(text).unwrap();
+        assert_eq!(chart.title.as_deref(), Some("Song"));
+        assert_eq!(chart.subtitle.as_deref(), Some("[HYPER]"));
+        assert_eq!(chart.artist, None);
+        assert_eq!(chart.genre.as_deref(), Some("Trance"));
+        assert_eq!(chart.playlevel.as_deref(), Some("12"));
+        assert_eq!(chart.total, Some(400.0));
+        assert_eq!(chart.rank, Some(100));
+        assert_eq!(chart.bpm, Some(174.5));
+        assert!(!chart.random);
+        assert_eq!(
+            paths(&chart, ResourceKind::Sound),
+            ["kick.wav", "sub/snare.ogg"]
+        );
+        assert_eq!(paths(&chart, ResourceKind::Image), ["bga.mp4"]);
+        assert!(parse_bmson("not json").is_err());
+    }
+}
@@ -0,0 +1,222 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::manifest;
+use crate::verify;
+
+const INDEX_FILE: &str = "index.json";
+
+/// Download cache shared across runs and output directories.
+///
+/// Archives are stored once under `blobs/<sha256>` and looked up by the URL they were
+/// downloaded from, or by the ETag and length the server reports for them.
+pub struct Cache {
+    root: PathBuf,
+    /// Total size of the blobs above which the least recently used are evicted
+    max_size: u64,
+    index: Mutex<CacheIndex>,
+}
+
+#[derive(Default, Serialize, Deserialize)]
+struct CacheIndex {
+    urls: HashMap<String, CachedUrl>,
+    blobs: HashMap<String, Blob>,
+}
+
+/// An archive downloaded from a URL
+#[derive(Clone, Serialize, Deserialize)]
+struct CachedUrl {
+    sha256: String,
+    filename: String,
+    etag: Option<String>,
+    length: u64,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Blob {
+    size: u64,
+    /// Seconds since the Unix epoch
+    last_used: u64,
+}
+
+impl Cache {
+    pub fn open(root: &Path, max_size: u64) -> Result<Self> {
+        fs::create_dir_all(root.join("blobs"))
+            .with_context(|| format!("failed to create cache {}", root.display()))?;
+        let index: CacheIndex = fs::read_to_string(root.join(INDEX_FILE))
+            .ok()
+            .and_then(|text| serde_json::from_str(&text).ok())
+            .unwrap_or_default();
+        tracing::debug!(
+            "cache {} holds {} archives",
+            root.display(),
+            index.blobs.len()
+        );
+
+        Ok(Self {
+            root: root.to_path_buf(),
+            max_size,
+            index: Mutex::new(index),
+        })
+    }
+
+    /// Place the archive previously downloaded from `url` in `output_dir`.
+    pub fn restore(&self, url: &str, output_dir: &Path) -> Option<PathBuf> {
+        let cached = self.index.lock().unwrap().urls.get(url).cloned()?;
+        self.place(&cached, output_dir)
+    }
+
+    /// Place an archive with the same strong ETag and length as a response from `url`
+    /// in `output_dir` under `filename`, remembering it for `url`.
+    pub fn restore_matching(
+        &self,
+        url: &str,
+        etag: &str,
+        length: u64,
+        filename: &str,
+        output_dir: &Path,
+    ) -> Option<PathBuf> {
+        if etag.starts_with("W/") {
+            return None;
+        }
+        let cached = {
+            let index = self.index.lock().unwrap();
+            let cached = index
+                .urls
+                .values()
+                .find(|c| c.etag.as_deref() == Some(etag) && c.length == length)?;
+            CachedUrl {
+                filename: filename.to_string(),
+                ..cached.clone()
+            }
+        };
+        let path = self.place(&cached, output_dir)?;
+
+        let mut index = self.index.lock().unwrap();
+        index.urls.insert(url.to_string(), cached);
+        self.save(&index);
+        Some(path)
+    }
+
+    /// Add an archive downloaded from `url`, then evict the least recently used
+    /// archives beyond the size limit.
+    pub fn insert(&self, url: &str, path: &Path, etag: Option<String>) -> Result<()> {
+        let sha256 = verify::hash_file(path)?.sha256;
+        let size = fs::metadata(path)?.len();
+        let blob = self.blob_path(&sha256);
+        if !blob.exists() {
+            let partial = blob.with_extension("part");
+            fs::copy(path, &partial)?;
+            fs::rename(&partial, &blob)?;
+        }
+
+        let mut index = self.index.lock().unwrap();
+        index.blobs.insert(
+            sha256.clone(),
+            Blob {
+                size,
+                last_used: manifest::now(),
+            },
+        );
+        index.urls.insert(
+            url.to_string(),
+            CachedUrl {
+                sha256: sha256.clone(),
+                filename: path
+                    .file_name()
+                    .map(|n| n.to_string_lossy().into_owned())
+                    .unwrap_or_default(),
+                etag,
+                length: size,
+            },
+        );
+        self.evict(&mut index, &sha256);
+        self.save(&index);
+
+        Ok(())
+    }
+
+    /// Drop the archive cached for `url`, e.g. after it failed to extract or lacked the
+    /// expected charts, so that the next run downloads it again.
+    pub fn forget(&self, url: &str) {
+        let mut index = self.index.lock().unwrap();
+        let Some(cached) = index.urls.remove(url) else {
+            return;
+        };
+        tracing::info!("removing {url} from cache");
+        if !index.urls.values().any(|c| c.sha256 == cached.sha256) {
+            let _ = fs::remove_file(self.blob_path(&cached.sha256));
+            index.blobs.remove(&cached.sha256);
+        }
+        self.save(&index);
+    }
+
+    /// Link or copy a cached archive into `output_dir`, dropping the entry if its blob is gone.
+    fn place(&self, cached: &CachedUrl, output_dir: &Path) -> Option<PathBuf> {
+        let blob = self.blob_path(&cached.sha256);
+        if !blob.is_file() {
+            let mut index = self.index.lock().unwrap();
+            index.urls.retain(|_, c| c.sha256 != cached.sha256);
+            index.blobs.remove(&cached.sha256);
+            self.save(&index);
+            return None;
+        }
+
+        let dest = output_dir.join(&cached.filename);
+        let _ = fs::remove_file(&dest);
+        if fs::hard_link(&blob, &dest).is_err()
+            && let Err(e) = fs::copy(&blob, &dest)
+        {
+            tracing::warn!("failed to copy {} from cache: {e}", cached.filename);
+            return None;
+        }
+
+        let mut index = self.index.lock().unwrap();
+        if let Some(entry) = index.blobs.get_mut(&cached.sha256) {
+            entry.last_used = manifest::now();
+        }
+        self.save(&index);
+        Some(dest)
+    }
+
+    fn evict(&self, index: &mut CacheIndex, keep: &str) {
+        let mut total: u64 = index.blobs.values().map(|b| b.size).sum();
+        let mut blobs: Vec<(String, u64, u64)> = index
+            .blobs
+            .iter()
+            .filter(|(sha256, _)| sha256.as_str() != keep)
+            .map(|(sha256, b)| (sha256.clone(), b.size, b.last_used))
+            .collect();
+        blobs.sort_by_key(|(_, _, last_used)| *last_used);
+
+        for (sha256, size, _) in blobs {
+            if total <= self.max_size {
+                break;
+            }
+            tracing::info!("evicting {sha256} from cache");
+            let _ = fs::remove_file(self.blob_path(&sha256));
+            index.blobs.remove(&sha256);
+            index.urls.retain(|_, c| c.sha256 != sha256);
+            total -= size;
+        }
+    }
+
+    fn blob_path(&self, sha256: &str) -> PathBuf {
+        self.root.join("blobs").join(sha256)
+    }
+
+    fn save(&self, index: &CacheIndex) {
+        let path = self.root.join(INDEX_FILE);
+        let result = serde_json::to_string(index)
+            .map_err(anyhow::Error::from)
+            .and_then(|text| fs::write(&path, text).map_err(anyhow::Error::from));
+        if let Err(e) = result {
+            tracing::warn!("failed to write {}: {e}", path.display());
+        }
+    }
+}
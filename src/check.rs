@@ -0,0 +1,112 @@
+use std::path::Path;
+use std::process::ExitCode;
+
+use anyhow::Result;
+
+use crate::cli::Args;
+use crate::download::{self, DownloadTask};
+use crate::error::ErrorKind;
+use crate::outcome::{self, Failure, exit_code};
+use crate::pipeline::download_options;
+use crate::report::{CheckRecord, CheckReport};
+
+/// Resolve the tasks' URLs and probe each resolved URL for its status, size and
+/// archive format, then print the dead links and save the results.
+pub async fn check_links(
+    client: &reqwest::Client,
+    args: &Args,
+    output_dir: &Path,
+    tasks: Vec<DownloadTask>,
+) -> Result<ExitCode> {
+    let previews = download::resolve_only(client, tasks, download_options(args)?).await;
+    let urls = previews
+        .iter()
+        .filter_map(|p| p.result.as_ref().ok().cloned())
+        .collect();
+    let mut probes = download::probe_all(client, urls, download_options(args)?)
+        .await
+        .into_iter();
+
+    let mut report = CheckReport::default();
+    let mut dead = Vec::new();
+
+    for preview in previews {
+        let mut record = CheckRecord {
+            directory: preview
+                .task
+                .output_dir
+                .strip_prefix(output_dir)
+                .unwrap_or(&preview.task.output_dir)
+                .to_string_lossy()
+                .into_owned(),
+            url: preview.task.url,
+            resolver: preview.resolver,
+            resolved_url: None,
+            status: None,
+            content_type: None,
+            content_length: None,
+            archive_format: None,
+            healthy: false,
+            error_category: None,
+            error: None,
+        };
+
+        let failure = match preview.result {
+            Err(failure) => Some(failure),
+            Ok(resolved_url) => {
+                record.resolved_url = Some(resolved_url);
+                match probes.next().expect("one probe per resolved URL") {
+                    Err(failure) => Some(failure),
+                    Ok(probe) => {
+                        record.status = Some(probe.status);
+                        record.content_type = probe.content_type;
+                        record.content_length = probe.content_length;
+                        record.archive_format = probe.archive_format.map(|f| f.as_str());
+
+                        let status = reqwest::StatusCode::from_u16(probe.status)?;
+                        if !status.is_success() {
+                            Some((ErrorKind::from_status(status), format!("HTTP {status}")))
+                        } else if probe.is_html {
+                            Some((
+                                ErrorKind::HtmlInsteadOfArchive,
+                                "server returned HTML instead of archive file".to_string(),
+                            ))
+                        } else {
+                            None
+                        }
+                    }
+                }
+            }
+        };
+
+        match failure {
+            Some((kind, error)) => {
+                dead.push(Failure {
+                    url: record.url.clone(),
+                    kind,
+                    message: error.clone(),
+                });
+                record.error_category = Some(kind);
+                record.error = Some(error);
+            }
+            None => record.healthy = true,
+        }
+        report.tasks.push(record);
+    }
+
+    println!();
+    println!("=== Check ===");
+    println!("  Healthy: {}", report.tasks.len() - dead.len());
+    println!("  Dead:    {}", dead.len());
+
+    outcome::print_by_cause(dead.iter().map(|f| f.kind));
+    outcome::print_failures("Dead links", &dead);
+
+    report.save(output_dir)?;
+    tracing::info!(
+        "check written to {}",
+        CheckReport::path(output_dir).display()
+    );
+
+    Ok(exit_code(dead.iter().map(|f| f.kind)))
+}
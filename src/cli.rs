@@ -1,11 +1,20 @@
 use clap::Parser;
 
+use crate::naming::{self, DirTemplate};
+use crate::store::LinkMode;
+use crate::throttle::{self, HostLimit};
+
 /// BMS difficulty table downloader
 #[derive(Parser)]
 #[command(version, about)]
 pub struct Args {
-    /// BMS table URL (e.g. https://stellabms.xyz/sl/table.html)
-    pub table_url: String,
+    /// BMS table URLs (e.g. https://stellabms.xyz/sl/table.html)
+    #[arg(required_unless_present_any = ["table_list", "retry"])]
+    pub table_urls: Vec<String>,
+
+    /// File listing table URLs, one per line
+    #[arg(long)]
+    pub table_list: Option<String>,
 
     /// Output directory
     #[arg(short, long, default_value = ".")]
@@ -15,6 +24,68 @@ pub struct Args {
     #[arg(short, long, default_value_t = 8)]
     pub jobs: usize,
 
+    /// Number of retries for a failed download
+    #[arg(long, default_value_t = 2)]
+    pub retries: u32,
+
+    /// Maximum delay between retries in seconds, including server-requested delays
+    #[arg(long, default_value_t = 60)]
+    pub max_backoff: u64,
+
+    /// Maximum concurrent requests per host [default: unlimited]
+    #[arg(long)]
+    pub per_host_jobs: Option<usize>,
+
+    /// Minimum delay between requests to the same host, in milliseconds
+    #[arg(long, default_value_t = 0)]
+    pub host_delay: u64,
+
+    /// Per-domain limit overriding the defaults (e.g. manbow.nothing.sh=2:1000)
+    #[arg(long, value_name = "DOMAIN=JOBS[:DELAY_MS]", value_parser = throttle::parse_host_limit)]
+    pub host_limit: Vec<(String, HostLimit)>,
+
+    /// Directory caching downloaded archives across runs and output directories
+    #[arg(long, value_name = "DIR", env = "BMS_DL_CACHE_DIR")]
+    pub cache_dir: Option<String>,
+
+    /// Maximum size of the download cache in MiB; least recently used archives are evicted
+    #[arg(long, value_name = "MIB", default_value_t = 10240)]
+    pub cache_size: u64,
+
+    /// beatoraja songdata.db; charts already in it are skipped as owned
+    #[arg(long, value_name = "FILE")]
+    pub beatoraja_db: Option<String>,
+
+    /// LR2 song.db; charts already in it are skipped as owned
+    #[arg(long, value_name = "FILE")]
+    pub lr2_db: Option<String>,
+
+    /// Local BMS library to scan; charts already in it are skipped as owned (repeatable)
+    #[arg(long, value_name = "DIR")]
+    pub library: Vec<String>,
+
+    /// For songs already in the library that lack a table's diff, download only the diff
+    /// into the song's folder
+    #[arg(long, conflicts_with_all = ["dry_run", "check", "import", "retry"])]
+    pub fill_diffs: bool,
+
+    /// Keep each song once in this directory and link it into the output directory
+    #[arg(long, value_name = "DIR", conflicts_with = "import")]
+    pub store: Option<String>,
+
+    /// How songs in the store are linked into the output directory
+    #[arg(long, value_enum, default_value_t = LinkMode::Hardlink, requires = "store")]
+    pub link_mode: LinkMode,
+
+    /// JSON file mapping md5/sha256 or directory names to replacement URLs or local archives
+    #[arg(long)]
+    pub overrides: Option<String>,
+
+    /// Entry directory name; placeholders: {symbol} {level} {title} {artist} {md5} {sha256}
+    /// {table}, and `/` creates subdirectories; {title}, {md5} or {sha256} is required
+    #[arg(long, value_name = "TEMPLATE", default_value = "{symbol}{level}_{title}", value_parser = naming::parse_dir_template)]
+    pub dir_template: DirTemplate,
+
     /// Skip downloading diffs
     #[arg(long)]
     pub no_diff: bool,
@@ -26,4 +97,25 @@ pub struct Args {
     /// Skip entries that already exist in the output directory
     #[arg(long)]
     pub skip_existing: bool,
+
+    /// Only download entries added or changed since the previous run
+    #[arg(long)]
+    pub sync: bool,
+
+    /// Import archives downloaded by hand from a directory instead of downloading
+    #[arg(long, value_name = "DIR", conflicts_with_all = ["retry", "sync"])]
+    pub import: Option<String>,
+
+    /// Resolve URLs and report which resolver handled them, without downloading
+    #[arg(long, conflicts_with_all = ["import", "retry"])]
+    pub dry_run: bool,
+
+    /// Probe each resolved URL (status, size, archive format) and report dead links
+    #[arg(long, conflicts_with_all = ["import", "retry", "dry_run"])]
+    pub check: bool,
+
+    /// Retry the entries that failed (or were skipped with a transient error or now have an
+    /// override) in the previous run, as recorded in report.json
+    #[arg(long, conflicts_with_all = ["table_urls", "table_list", "sync"])]
+    pub retry: bool,
 }
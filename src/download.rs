@@ -1,77 +1,153 @@
 use std::path::{Path, PathBuf};
 use std::sync::Arc;
+use std::time::Duration;
 
 use anyhow::{Context, Result};
 use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
-use reqwest::header;
+use reqwest::{StatusCode, header};
+use serde::{Deserialize, Serialize};
 use tokio::io::AsyncWriteExt;
 use tokio::sync::Semaphore;
 
-use crate::archive;
-use crate::resolve::{self, ResolvedUrl};
+use crate::archive::{self, ArchiveFormat};
+use crate::cache::Cache;
+use crate::error::{self, ErrorKind, RateLimited};
+use crate::resolve::{self, ResolvedUrl, Resolver};
+use crate::throttle::HostLimiter;
 
 /// Result of a single download task
 #[derive(Debug)]
 pub enum DownloadResult {
-    Success { path: PathBuf },
-    Skipped { url: String, reason: String },
-    Failed { url: String, error: String },
+    Success {
+        path: PathBuf,
+        url: String,
+        resolved_url: String,
+        bytes: u64,
+    },
+    Skipped {
+        url: String,
+        output_dir: PathBuf,
+        kind: ErrorKind,
+        reason: String,
+    },
+    Failed {
+        url: String,
+        output_dir: PathBuf,
+        resolved_url: Option<String>,
+        kind: ErrorKind,
+        error: String,
+    },
 }
 
-/// Whether a download error is worth retrying.
-/// HTTP 4xx and content-type mismatches are deterministic — retrying won't help.
-fn is_retryable(err: &anyhow::Error) -> bool {
-    let msg = err.to_string();
+/// Settings shared by all download tasks
+pub struct DownloadOptions {
+    /// Number of concurrent downloads
+    pub jobs: usize,
+    /// Number of retries after the first failed attempt
+    pub retries: u32,
+    /// Upper bound for the delay between attempts, including server-requested delays
+    pub max_backoff: Duration,
+    pub limiter: Arc<HostLimiter>,
+    pub cache: Option<Arc<Cache>>,
+}
 
-    // HTTP 4xx errors are deterministic — file doesn't exist or access denied
-    if msg.contains("status client error") {
-        return false;
+/// Turn 429/503 responses into [`RateLimited`] and other error statuses into errors.
+fn check_status(resp: reqwest::Response) -> Result<reqwest::Response> {
+    let status = resp.status();
+    if status == StatusCode::TOO_MANY_REQUESTS || status == StatusCode::SERVICE_UNAVAILABLE {
+        let retry_after = header_string(&resp, header::RETRY_AFTER)
+            .as_deref()
+            .and_then(parse_retry_after);
+        return Err(RateLimited {
+            status,
+            retry_after,
+        }
+        .into());
     }
 
-    // Content-type mismatch — server consistently returns HTML
-    if msg.contains("server returned HTML")
-        || msg.contains("Dropbox file has been removed")
-        || msg.contains("Google Drive file requires authentication")
-        || msg.contains("Google Drive returned HTML confirmation")
-        || msg.contains("downloaded file is HTML")
-    {
-        return false;
+    Ok(resp.error_for_status()?)
+}
+
+/// Parse a `Retry-After` value given either as seconds or as an HTTP date.
+fn parse_retry_after(value: &str) -> Option<Duration> {
+    let value = value.trim();
+    if let Ok(secs) = value.parse::<u64>() {
+        return Some(Duration::from_secs(secs));
     }
 
-    // Everything else (5xx, network, timeout) is worth retrying
-    true
+    let date = httpdate::parse_http_date(value).ok()?;
+    Some(
+        date.duration_since(std::time::SystemTime::now())
+            .unwrap_or_default(),
+    )
 }
 
 /// Download a file from a resolved URL to the given directory.
+///
+/// Failed attempts are retried with exponential backoff. When the server rate limits
+/// us, its `Retry-After` delay is used instead and applied to the whole host so that
+/// other queued tasks for it wait as well.
 async fn download_file(
     client: &reqwest::Client,
     resolved: &ResolvedUrl,
     output_dir: &Path,
     fallback_name: &str,
+    options: &DownloadOptions,
     pb: &ProgressBar,
 ) -> Result<PathBuf> {
+    let cached = match options.cache {
+        Some(ref cache) => {
+            let (url, dir) = (resolved.url.clone(), output_dir.to_path_buf());
+            with_cache(cache, move |cache| cache.restore(&url, &dir)).await
+        }
+        None => None,
+    };
+    if let Some(path) = cached {
+        tracing::info!("using cached archive for {}", resolved.url);
+        pb.set_message("cached");
+        return Ok(path);
+    }
+
+    let attempts = options.retries + 1;
+    let mut delay = Duration::ZERO;
     let mut last_error = None;
 
-    for attempt in 0..3 {
+    for attempt in 0..attempts {
         if attempt > 0 {
-            let delay = std::time::Duration::from_secs(1 << (2 * attempt));
-            pb.set_message(format!("retry {attempt}/3 in {}s...", delay.as_secs()));
+            pb.set_message(format!(
+                "retry {attempt}/{} in {}s...",
+                options.retries,
+                delay.as_secs()
+            ));
             tokio::time::sleep(delay).await;
         }
+        options.limiter.throttle(&resolved.url).await;
 
-        match try_download(client, &resolved.url, output_dir, fallback_name, pb).await {
+        let cache = options.cache.as_ref();
+        match try_download(client, &resolved.url, output_dir, fallback_name, cache, pb).await {
             Ok(path) => return Ok(path),
             Err(e) => {
                 tracing::warn!(
                     "download attempt {}/{} failed for {} (resolved: {}): {e}",
                     attempt + 1,
-                    3,
+                    attempts,
                     resolved.original,
                     resolved.url,
                 );
-                if !is_retryable(&e) {
+                if !error::classify(&e).is_retryable() {
                     return Err(e);
                 }
+
+                let backoff = Duration::from_secs(4u64.saturating_pow(attempt + 1));
+                delay = match e.downcast_ref::<RateLimited>() {
+                    Some(rate_limited) => {
+                        let wait = rate_limited.retry_after.unwrap_or(backoff);
+                        let wait = wait.min(options.max_backoff);
+                        options.limiter.pause(&resolved.url, wait);
+                        wait
+                    }
+                    None => backoff.min(options.max_backoff),
+                };
                 last_error = Some(e);
             }
         }
@@ -85,11 +161,19 @@ async fn try_download(
     url: &str,
     output_dir: &Path,
     fallback_name: &str,
+    cache: Option<&Arc<Cache>>,
     pb: &ProgressBar,
 ) -> Result<PathBuf> {
-    let resp = client.get(url).send().await?.error_for_status()?;
+    if let Ok(parsed) = url::Url::parse(url)
+        && parsed.scheme() == "file"
+    {
+        return copy_local_archive(&parsed, output_dir, pb).await;
+    }
+
+    let partial = PartialDownload::new(url, output_dir, fallback_name);
+    let resp = send_resumable(client, url, &partial).await?;
 
-    // Check if this is a Google Drive virus scan confirmation page
+    // Follow HTML pages such as the Google Drive virus scan confirmation
     let content_type = resp
         .headers()
         .get(header::CONTENT_TYPE)
@@ -98,93 +182,315 @@ async fn try_download(
         .to_string();
 
     if content_type.contains("text/html") {
-        if is_google_drive_url(url) {
-            let html_body = resp.text().await?;
-            if let Some(confirm_url) = extract_gdrive_confirm_url(&html_body) {
-                tracing::info!("Google Drive virus scan detected, following confirmation URL");
-                let resp2 = client.get(&confirm_url).send().await?.error_for_status()?;
-                return save_response(resp2, output_dir, fallback_name, pb).await;
-            }
-            // Detect Google login redirect (file is deleted or private)
-            if html_body.contains("accounts.google.com") || html_body.contains("ServiceLogin") {
-                return Err(anyhow::anyhow!(
-                    "Google Drive file requires authentication (likely deleted or private)"
-                ));
-            }
-            return Err(anyhow::anyhow!(
-                "Google Drive returned HTML confirmation page but could not extract download URL"
-            ));
-        }
-
-        // Non-Google-Drive URL returned HTML — detect specific hosting service errors
         let html_body = resp.text().await?;
+        let download_url = follow_html_page(client, url, &html_body).await?;
+        let resp = send_resumable(client, &download_url, &partial).await?;
+        return save_response(resp, url, output_dir, &partial, fallback_name, cache, pb).await;
+    }
 
-        if (url.contains("dropbox.com") || url.contains("dropboxusercontent.com"))
-            && (html_body.contains("doesn't exist")
-                || html_body.contains("has been removed")
-                || html_body.contains("Error (404)"))
-        {
-            return Err(anyhow::anyhow!(
-                "Dropbox file has been removed or does not exist"
+    save_response(resp, url, output_dir, &partial, fallback_name, cache, pb).await
+}
+
+/// URL to fetch instead of an HTML page served for `url`: the Google Drive virus scan
+/// confirmation URL, or a download link found on the page.
+///
+/// Fails when the page shows that the file is gone or private, or links to no download.
+async fn follow_html_page(client: &reqwest::Client, url: &str, html_body: &str) -> Result<String> {
+    if is_google_drive_url(url) {
+        if let Some(confirm_url) = extract_gdrive_confirm_url(html_body) {
+            tracing::info!("Google Drive virus scan detected, following confirmation URL");
+            return Ok(confirm_url);
+        }
+        // Detect Google login redirect (file is deleted or private)
+        if html_body.contains("accounts.google.com") || html_body.contains("ServiceLogin") {
+            return Err(error::error(
+                ErrorKind::AuthRequired,
+                "Google Drive file requires authentication (likely deleted or private)",
             ));
         }
+        return Err(error::error(
+            ErrorKind::HtmlInsteadOfArchive,
+            "Google Drive returned HTML confirmation page but could not extract download URL",
+        ));
+    }
 
-        // Try secondary resolution: extract download links from the HTML page
-        if let Ok(base_url) = url::Url::parse(url)
-            && let Ok(links) = resolve::extract_links_from_html(&html_body, &base_url)
+    // Non-Google-Drive URL returned HTML — detect specific hosting service errors
+    if (url.contains("dropbox.com") || url.contains("dropboxusercontent.com"))
+        && (html_body.contains("doesn't exist")
+            || html_body.contains("has been removed")
+            || html_body.contains("Error (404)"))
+    {
+        return Err(error::error(
+            ErrorKind::DeadLink,
+            "Dropbox file has been removed or does not exist",
+        ));
+    }
+
+    // Try secondary resolution: extract download links from the HTML page
+    if let Ok(base_url) = url::Url::parse(url)
+        && let Ok(links) = resolve::extract_links_from_html(html_body, &base_url)
+    {
+        let no_redirect_client = reqwest::Client::builder()
+            .redirect(reqwest::redirect::Policy::limited(10))
+            .connect_timeout(std::time::Duration::from_secs(10))
+            .timeout(std::time::Duration::from_secs(300))
+            .build()
+            .unwrap_or_else(|_| client.clone());
+        if let Some(Ok(resolved)) =
+            resolve::find_download_from_candidates(&no_redirect_client, &links, url).await
         {
-            let no_redirect_client = reqwest::Client::builder()
-                .redirect(reqwest::redirect::Policy::limited(10))
-                .connect_timeout(std::time::Duration::from_secs(10))
-                .timeout(std::time::Duration::from_secs(300))
-                .build()
-                .unwrap_or_else(|_| client.clone());
-            if let Some(Ok(resolved)) =
-                resolve::find_download_from_candidates(&no_redirect_client, &links, url).await
-            {
-                tracing::info!(
-                    "secondary resolution found download link: {} -> {}",
-                    url,
-                    resolved.url
-                );
-                return save_response(
-                    client.get(&resolved.url).send().await?.error_for_status()?,
-                    output_dir,
-                    fallback_name,
-                    pb,
-                )
-                .await;
-            }
+            tracing::info!(
+                "secondary resolution found download link: {} -> {}",
+                url,
+                resolved.url
+            );
+            return Ok(resolved.url);
         }
+    }
 
-        return Err(anyhow::anyhow!(
-            "server returned HTML instead of archive file (Content-Type: text/html)"
+    Err(error::error(
+        ErrorKind::HtmlInsteadOfArchive,
+        "server returned HTML instead of archive file (Content-Type: text/html)",
+    ))
+}
+
+/// Copy a local archive given as a `file://` URL into the output directory.
+async fn copy_local_archive(
+    url: &url::Url,
+    output_dir: &Path,
+    pb: &ProgressBar,
+) -> Result<PathBuf> {
+    let source = url
+        .to_file_path()
+        .map_err(|()| error::error(ErrorKind::InvalidUrl, format!("invalid file URL: {url}")))?;
+    if !source.is_file() {
+        return Err(error::error(
+            ErrorKind::DeadLink,
+            format!("local archive not found: {}", source.display()),
         ));
     }
 
-    save_response(resp, output_dir, fallback_name, pb).await
+    let filename = source
+        .file_name()
+        .map(|n| sanitize_filename(&n.to_string_lossy()))
+        .unwrap_or_default();
+    let dest = output_dir.join(&filename);
+    pb.set_message(filename);
+
+    let bytes = tokio::fs::copy(&source, &dest)
+        .await
+        .with_context(|| format!("failed to copy {}", source.display()))?;
+    pb.set_length(bytes);
+    pb.set_position(bytes);
+
+    Ok(dest)
+}
+
+/// Partially downloaded file kept across retries and runs so it can be resumed
+/// with a `Range` request.
+///
+/// The partial file is named after the task's fallback name because the real
+/// filename is only known once the server responds. It belongs to the task's resolved
+/// URL, so it is resumed through confirmation URLs that change between requests.
+struct PartialDownload {
+    url: String,
+    path: PathBuf,
+    state_path: PathBuf,
+}
+
+/// Validators recorded when a partial download was started
+#[derive(Debug, Serialize, Deserialize)]
+struct PartialState {
+    /// Resolved URL of the task
+    url: String,
+    filename: String,
+    etag: Option<String>,
+    last_modified: Option<String>,
+}
+
+impl PartialDownload {
+    fn new(url: &str, output_dir: &Path, fallback_name: &str) -> Self {
+        Self {
+            url: url.to_string(),
+            path: output_dir.join(format!(".{fallback_name}.part")),
+            state_path: output_dir.join(format!(".{fallback_name}.part.json")),
+        }
+    }
+
+    fn load_state(&self) -> Option<PartialState> {
+        let text = std::fs::read_to_string(&self.state_path).ok()?;
+        serde_json::from_str(&text).ok()
+    }
+
+    fn save_state(&self, state: &PartialState) -> Result<()> {
+        std::fs::write(&self.state_path, serde_json::to_string(state)?)
+            .context("failed to write partial download state")
+    }
+
+    /// Byte offset to resume from and the `If-Range` validator, if a partial file
+    /// for this task exists and the server gave us a validator for it.
+    fn resume_point(&self) -> Option<(u64, String)> {
+        let state = self.load_state().filter(|s| s.url == self.url)?;
+        // Weak ETags are not allowed in If-Range
+        let validator = state
+            .etag
+            .filter(|e| !e.starts_with("W/"))
+            .or(state.last_modified)?;
+        let len = std::fs::metadata(&self.path).ok()?.len();
+        (len > 0).then_some((len, validator))
+    }
+
+    fn discard(&self) {
+        let _ = std::fs::remove_file(&self.path);
+        let _ = std::fs::remove_file(&self.state_path);
+    }
+}
+
+/// Send a GET request, asking to resume a matching partial download if there is one.
+async fn send_resumable(
+    client: &reqwest::Client,
+    url: &str,
+    partial: &PartialDownload,
+) -> Result<reqwest::Response> {
+    let mut request = client.get(url);
+    if let Some((offset, validator)) = partial.resume_point() {
+        tracing::info!("resuming {url} from byte {offset}");
+        request = request
+            .header(header::RANGE, format!("bytes={offset}-"))
+            .header(header::IF_RANGE, validator);
+    }
+
+    let resp = request.send().await?;
+    if resp.status() == StatusCode::RANGE_NOT_SATISFIABLE {
+        tracing::warn!("server rejected resume range for {url}, restarting download");
+        partial.discard();
+        return check_status(client.get(url).send().await?);
+    }
+
+    check_status(resp)
 }
 
+fn header_string(resp: &reqwest::Response, name: header::HeaderName) -> Option<String> {
+    resp.headers()
+        .get(name)
+        .and_then(|v| v.to_str().ok())
+        .map(String::from)
+}
+
+/// Parse the start offset of a `Content-Range: bytes START-END/TOTAL` header.
+fn content_range_start(resp: &reqwest::Response) -> Option<u64> {
+    resp.headers()
+        .get(header::CONTENT_RANGE)?
+        .to_str()
+        .ok()?
+        .strip_prefix("bytes ")?
+        .split('-')
+        .next()?
+        .parse()
+        .ok()
+}
+
+/// Parse the total size of a `Content-Range: bytes START-END/TOTAL` header.
+fn content_range_total(resp: &reqwest::Response) -> Option<u64> {
+    resp.headers()
+        .get(header::CONTENT_RANGE)?
+        .to_str()
+        .ok()?
+        .rsplit('/')
+        .next()?
+        .parse()
+        .ok()
+}
+
+/// Save a response to `output_dir`, resuming the partial download if it continues it.
+///
+/// `url` is the task's resolved URL: the cache remembers the archive under it even when
+/// the response came from a confirmation page or a link found on the resolved page.
 async fn save_response(
     resp: reqwest::Response,
+    url: &str,
     output_dir: &Path,
+    partial: &PartialDownload,
     fallback_name: &str,
+    cache: Option<&Arc<Cache>>,
     pb: &ProgressBar,
 ) -> Result<PathBuf> {
-    let filename =
-        extract_filename(&resp, resp.url().as_str()).unwrap_or_else(|| fallback_name.to_string());
+    let existing = std::fs::metadata(&partial.path)
+        .map(|m| m.len())
+        .unwrap_or(0);
+
+    // Resume only if the server honored our range exactly; otherwise start over
+    let resumed_state = if resp.status() == StatusCode::PARTIAL_CONTENT {
+        let state = partial.load_state();
+        if state.is_none() || content_range_start(&resp) != Some(existing) {
+            partial.discard();
+            return Err(error::error(
+                ErrorKind::Network,
+                "server returned unexpected Content-Range for resumed download",
+            ));
+        }
+        state
+    } else {
+        None
+    };
+    let resumed = resumed_state.is_some();
+    let etag = header_string(&resp, header::ETAG);
+
+    // The same archive may already be cached under another URL
+    if !resumed
+        && let Some(cache) = cache
+        && let Some(ref etag) = etag
+        && let Some(length) = resp.content_length()
+    {
+        let filename = extract_filename(&resp, resp.url().as_str())
+            .unwrap_or_else(|| fallback_name.to_string());
+        let (key, etag, dir) = (url.to_string(), etag.clone(), output_dir.to_path_buf());
+        let restored = with_cache(cache, move |cache| {
+            cache.restore_matching(&key, &etag, length, &filename, &dir)
+        })
+        .await;
+        if let Some(path) = restored {
+            tracing::info!("using cached archive with matching ETag for {url}");
+            partial.discard();
+            return Ok(path);
+        }
+    }
+
+    let filename = match resumed_state {
+        Some(state) => state.filename,
+        None => {
+            let filename = extract_filename(&resp, resp.url().as_str())
+                .unwrap_or_else(|| fallback_name.to_string());
+            partial.save_state(&PartialState {
+                url: partial.url.clone(),
+                filename: filename.clone(),
+                etag: etag.clone(),
+                last_modified: header_string(&resp, header::LAST_MODIFIED),
+            })?;
+            filename
+        }
+    };
     let dest = output_dir.join(&filename);
-    let tmp = output_dir.join(format!(".{filename}.tmp"));
 
     pb.set_message(filename.clone());
 
+    let offset = if resumed { existing } else { 0 };
     if let Some(len) = resp.content_length() {
-        pb.set_length(len);
+        pb.set_length(offset + len);
     }
+    pb.set_position(offset);
 
-    let mut file = tokio::fs::File::create(&tmp)
-        .await
-        .context("failed to create temp file")?;
+    let mut file = if resumed {
+        tokio::fs::OpenOptions::new()
+            .append(true)
+            .open(&partial.path)
+            .await
+            .context("failed to open partial file")?
+    } else {
+        tokio::fs::File::create(&partial.path)
+            .await
+            .context("failed to create temp file")?
+    };
 
     let mut stream = resp.bytes_stream();
     use futures_util::StreamExt;
@@ -197,19 +503,41 @@ async fn save_response(
     file.flush().await?;
     drop(file);
 
-    tokio::fs::rename(&tmp, &dest).await?;
+    tokio::fs::rename(&partial.path, &dest).await?;
+    partial.discard();
 
     // Validate downloaded content is not HTML
     if archive::is_html(&dest) {
         let _ = tokio::fs::remove_file(&dest).await;
-        return Err(anyhow::anyhow!(
-            "downloaded file is HTML, not an archive (possible redirect or error page)"
+        return Err(error::error(
+            ErrorKind::HtmlInsteadOfArchive,
+            "downloaded file is HTML, not an archive (possible redirect or error page)",
         ));
     }
 
+    if let Some(cache) = cache {
+        let (key, path) = (url.to_string(), dest.clone());
+        let error = with_cache(cache, move |cache| cache.insert(&key, &path, etag).err()).await;
+        if let Some(e) = error {
+            tracing::warn!("failed to cache {}: {e}", dest.display());
+        }
+    }
+
     Ok(dest)
 }
 
+/// Run a cache operation, which hashes and copies whole archives, off the async workers.
+async fn with_cache<T: Send + 'static>(
+    cache: &Arc<Cache>,
+    f: impl FnOnce(&Cache) -> Option<T> + Send + 'static,
+) -> Option<T> {
+    let cache = cache.clone();
+    tokio::task::spawn_blocking(move || f(&cache))
+        .await
+        .ok()
+        .flatten()
+}
+
 fn is_google_drive_url(url: &str) -> bool {
     url.contains("drive.google.com") || url.contains("drive.usercontent.google.com")
 }
@@ -342,42 +670,227 @@ enum ResolveResult {
         task: DownloadTask,
     },
     Skipped {
-        url: String,
+        task: DownloadTask,
+        kind: ErrorKind,
         reason: String,
     },
 }
 
-/// Execute all download tasks with concurrency control and progress display.
-///
-/// Phase 1: Resolve all URLs in parallel (with `jobs * 2` concurrency).
-/// Phase 2: Download resolved URLs in parallel (with `jobs` concurrency).
-pub async fn execute_downloads(
+/// Resolve every task's URL in the background (with `jobs * 2` concurrency).
+fn spawn_resolves(
     client: &reqwest::Client,
     tasks: Vec<DownloadTask>,
-    jobs: usize,
-) -> Vec<DownloadResult> {
-    // Phase 1: Resolve URLs
-    let resolve_semaphore = Arc::new(Semaphore::new(jobs * 2));
+    options: &Arc<DownloadOptions>,
+) -> Vec<tokio::task::JoinHandle<ResolveResult>> {
+    let resolve_semaphore = Arc::new(Semaphore::new(options.jobs * 2));
     let client_arc = Arc::new(client.clone());
     let mut resolve_handles = Vec::new();
 
     for task in tasks {
         let sem = resolve_semaphore.clone();
         let client = client_arc.clone();
+        let options = options.clone();
 
         resolve_handles.push(tokio::spawn(async move {
+            let _host_permit = options.limiter.acquire(&task.url).await;
             let _permit = sem.acquire().await.unwrap();
+            options.limiter.throttle(&task.url).await;
 
             match resolve::resolve_url(&client, &task.url).await {
                 Ok(resolved) => ResolveResult::Resolved { resolved, task },
                 Err(e) => ResolveResult::Skipped {
-                    url: task.url.clone(),
+                    kind: error::classify(&e),
                     reason: e.to_string(),
+                    task,
                 },
             }
         }));
     }
 
+    resolve_handles
+}
+
+/// Outcome of resolving a task's URL without downloading it
+pub struct ResolvePreview {
+    pub task: DownloadTask,
+    pub resolver: Option<Resolver>,
+    pub result: std::result::Result<String, (ErrorKind, String)>,
+}
+
+/// Resolve all tasks' URLs without downloading anything (dry run).
+pub async fn resolve_only(
+    client: &reqwest::Client,
+    tasks: Vec<DownloadTask>,
+    options: DownloadOptions,
+) -> Vec<ResolvePreview> {
+    let options = Arc::new(options);
+    let mut previews = Vec::new();
+
+    for handle in spawn_resolves(client, tasks, &options) {
+        let (task, result) = match handle.await {
+            Ok(ResolveResult::Resolved { resolved, task }) => (task, Ok(resolved.url)),
+            Ok(ResolveResult::Skipped { task, kind, reason }) => (task, Err((kind, reason))),
+            Err(e) => {
+                tracing::error!("resolve task panicked: {e}");
+                continue;
+            }
+        };
+        previews.push(ResolvePreview {
+            resolver: resolve::resolver_for(&task.url),
+            task,
+            result,
+        });
+    }
+
+    previews
+}
+
+/// Response of a resolved URL to a small range request
+pub struct Probe {
+    pub status: u16,
+    pub content_type: Option<String>,
+    /// Full size of the file, not of the requested range
+    pub content_length: Option<u64>,
+    pub archive_format: Option<ArchiveFormat>,
+    pub is_html: bool,
+}
+
+/// Number of leading bytes requested to detect the archive format
+const PROBE_BYTES: u64 = 512;
+
+/// Probe a URL without downloading the file.
+///
+/// An HTML page is followed like a download would (Google Drive confirmation, download
+/// links on the page) before the link is reported as serving HTML.
+async fn probe(client: &reqwest::Client, url: &str) -> Result<Probe> {
+    let probe = probe_once(client, url).await?;
+    let is_html_page = probe
+        .content_type
+        .as_deref()
+        .is_some_and(|t| t.contains("text/html"));
+    if !is_html_page || !StatusCode::from_u16(probe.status)?.is_success() {
+        return Ok(probe);
+    }
+
+    // The probe only read the first bytes of the page
+    let html_body = client.get(url).send().await?.text().await?;
+    let download_url = follow_html_page(client, url, &html_body).await?;
+    probe_once(client, &download_url).await
+}
+
+/// Probe a URL with a `Range` request for its first bytes.
+async fn probe_once(client: &reqwest::Client, url: &str) -> Result<Probe> {
+    if let Ok(parsed) = url::Url::parse(url)
+        && parsed.scheme() == "file"
+    {
+        let path = parsed.to_file_path().map_err(|()| {
+            error::error(ErrorKind::InvalidUrl, format!("invalid file URL: {url}"))
+        })?;
+        let metadata = std::fs::metadata(&path)
+            .map_err(|e| error::error(ErrorKind::DeadLink, format!("{}: {e}", path.display())))?;
+        let mut head = vec![0u8; PROBE_BYTES as usize];
+        let n = std::io::Read::read(&mut std::fs::File::open(&path)?, &mut head)?;
+        head.truncate(n);
+        return Ok(Probe {
+            status: 200,
+            content_type: None,
+            content_length: Some(metadata.len()),
+            archive_format: ArchiveFormat::from_magic(&head),
+            is_html: archive::is_html_bytes(&head),
+        });
+    }
+
+    let resp = client
+        .get(url)
+        .header(header::RANGE, format!("bytes=0-{}", PROBE_BYTES - 1))
+        .send()
+        .await?;
+    let status = resp.status();
+    let content_type = header_string(&resp, header::CONTENT_TYPE);
+    let content_length = if status == StatusCode::PARTIAL_CONTENT {
+        content_range_total(&resp)
+    } else {
+        resp.content_length()
+    };
+
+    // Read only the first chunks; servers ignoring the range would send the whole file
+    let mut head = Vec::new();
+    let mut stream = resp.bytes_stream();
+    use futures_util::StreamExt;
+    while (head.len() as u64) < PROBE_BYTES {
+        match stream.next().await {
+            Some(Ok(chunk)) => head.extend_from_slice(&chunk),
+            _ => break,
+        }
+    }
+    head.truncate(PROBE_BYTES as usize);
+
+    Ok(Probe {
+        status: status.as_u16(),
+        is_html: content_type
+            .as_deref()
+            .is_some_and(|t| t.contains("text/html"))
+            || archive::is_html_bytes(&head),
+        content_type,
+        content_length,
+        archive_format: ArchiveFormat::from_magic(&head),
+    })
+}
+
+/// Probe resolved URLs in parallel (with `jobs` concurrency and per-host limits),
+/// returning the results in the same order.
+pub async fn probe_all(
+    client: &reqwest::Client,
+    urls: Vec<String>,
+    options: DownloadOptions,
+) -> Vec<std::result::Result<Probe, (ErrorKind, String)>> {
+    let options = Arc::new(options);
+    let semaphore = Arc::new(Semaphore::new(options.jobs));
+    let mut handles = Vec::new();
+
+    for url in urls {
+        let sem = semaphore.clone();
+        let client = client.clone();
+        let options = options.clone();
+
+        handles.push(tokio::spawn(async move {
+            let _host_permit = options.limiter.acquire(&url).await;
+            let _permit = sem.acquire().await.unwrap();
+            options.limiter.throttle(&url).await;
+            probe(&client, &url)
+                .await
+                .map_err(|e| (error::classify(&e), e.to_string()))
+        }));
+    }
+
+    let mut results = Vec::new();
+    for handle in handles {
+        results.push(
+            handle
+                .await
+                .unwrap_or_else(|e| Err((ErrorKind::Other, format!("probe task panicked: {e}")))),
+        );
+    }
+
+    results
+}
+
+/// Execute all download tasks with concurrency control and progress display.
+///
+/// Phase 1: Resolve all URLs in parallel (with `jobs * 2` concurrency).
+/// Phase 2: Download resolved URLs in parallel (with `jobs` concurrency).
+///
+/// Both phases additionally respect the per-host limits of `limiter`.
+pub async fn execute_downloads(
+    client: &reqwest::Client,
+    tasks: Vec<DownloadTask>,
+    options: DownloadOptions,
+) -> Vec<DownloadResult> {
+    let options = Arc::new(options);
+
+    // Phase 1: Resolve URLs
+    let resolve_handles = spawn_resolves(client, tasks, &options);
+
     let mut resolved_tasks = Vec::new();
     let mut results = Vec::new();
 
@@ -386,13 +899,21 @@ pub async fn execute_downloads(
             Ok(ResolveResult::Resolved { resolved, task }) => {
                 resolved_tasks.push((resolved, task));
             }
-            Ok(ResolveResult::Skipped { url, reason }) => {
-                tracing::warn!("skipping {url}: {reason}");
-                results.push(DownloadResult::Skipped { url, reason });
+            Ok(ResolveResult::Skipped { task, kind, reason }) => {
+                tracing::warn!("skipping {}: {reason}", task.url);
+                results.push(DownloadResult::Skipped {
+                    url: task.url,
+                    output_dir: task.output_dir,
+                    kind,
+                    reason,
+                });
             }
             Err(e) => {
                 results.push(DownloadResult::Failed {
                     url: "unknown".to_string(),
+                    output_dir: PathBuf::new(),
+                    resolved_url: None,
+                    kind: ErrorKind::Other,
                     error: format!("resolve task panicked: {e}"),
                 });
             }
@@ -400,7 +921,8 @@ pub async fn execute_downloads(
     }
 
     // Phase 2: Download resolved URLs
-    let download_semaphore = Arc::new(Semaphore::new(jobs));
+    let client_arc = Arc::new(client.clone());
+    let download_semaphore = Arc::new(Semaphore::new(options.jobs));
     let multi_progress = MultiProgress::new();
     let style = ProgressStyle::with_template(
         "{spinner:.green} [{bar:30.cyan/blue}] {bytes}/{total_bytes} {msg}",
@@ -413,11 +935,15 @@ pub async fn execute_downloads(
     for (resolved, task) in resolved_tasks {
         let sem = download_semaphore.clone();
         let client = client_arc.clone();
+        let options = options.clone();
         let pb = multi_progress.add(ProgressBar::new(0));
         pb.set_style(style.clone());
         pb.set_message(task.label.clone());
 
         download_handles.push(tokio::spawn(async move {
+            // Wait for the host first so that tasks blocked on a busy host do not hold
+            // global slots other hosts could use
+            let _host_permit = options.limiter.acquire(&resolved.url).await;
             let _permit = sem.acquire().await.unwrap();
 
             // Create output directory
@@ -425,6 +951,9 @@ pub async fn execute_downloads(
                 pb.finish_with_message(format!("FAIL: {e}"));
                 return DownloadResult::Failed {
                     url: task.url.clone(),
+                    output_dir: task.output_dir.clone(),
+                    resolved_url: Some(resolved.url.clone()),
+                    kind: ErrorKind::Other,
                     error: e.to_string(),
                 };
             }
@@ -434,13 +963,20 @@ pub async fn execute_downloads(
                 &resolved,
                 &task.output_dir,
                 &task.fallback_name,
+                &options,
                 &pb,
             )
             .await
             {
                 Ok(path) => {
                     pb.finish_with_message("done");
-                    DownloadResult::Success { path }
+                    let bytes = std::fs::metadata(&path).map(|m| m.len()).unwrap_or(0);
+                    DownloadResult::Success {
+                        path,
+                        url: task.url.clone(),
+                        resolved_url: resolved.url.clone(),
+                        bytes,
+                    }
                 }
                 Err(e) => {
                     pb.finish_with_message(format!("FAIL: {e}"));
@@ -451,6 +987,9 @@ pub async fn execute_downloads(
                     };
                     DownloadResult::Failed {
                         url: task.url.clone(),
+                        output_dir: task.output_dir.clone(),
+                        resolved_url: Some(resolved.url.clone()),
+                        kind: error::classify(&e),
                         error,
                     }
                 }
@@ -463,6 +1002,9 @@ pub async fn execute_downloads(
             Ok(result) => results.push(result),
             Err(e) => results.push(DownloadResult::Failed {
                 url: "unknown".to_string(),
+                output_dir: PathBuf::new(),
+                resolved_url: None,
+                kind: ErrorKind::Other,
                 error: format!("task panicked: {e}"),
             }),
         }
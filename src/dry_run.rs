@@ -0,0 +1,76 @@
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::process::ExitCode;
+
+use anyhow::Result;
+
+use crate::cli::Args;
+use crate::download::{self, DownloadTask};
+use crate::outcome::{self, exit_code};
+use crate::pipeline::download_options;
+use crate::report::{DryRunReport, ResolveRecord};
+
+/// Resolve the tasks' URLs without downloading, then print and save which resolver
+/// handled each URL and what it resolved to.
+pub async fn dry_run(
+    client: &reqwest::Client,
+    args: &Args,
+    output_dir: &Path,
+    tasks: Vec<DownloadTask>,
+) -> Result<ExitCode> {
+    let previews = download::resolve_only(client, tasks, download_options(args)?).await;
+
+    let mut report = DryRunReport::default();
+    let mut by_resolver: BTreeMap<&str, usize> = BTreeMap::new();
+    let mut causes = Vec::new();
+
+    println!();
+    println!("=== Dry run ===");
+    for preview in previews {
+        let resolver = preview.resolver.map_or("invalid", |r| r.as_str());
+        *by_resolver.entry(resolver).or_default() += 1;
+
+        let (resolved_url, failure) = match preview.result {
+            Ok(resolved_url) => {
+                println!("  [{resolver}] {} -> {resolved_url}", preview.task.url);
+                (Some(resolved_url), None)
+            }
+            Err((kind, error)) => {
+                println!("  [{resolver}] {}\t[{kind}] {error}", preview.task.url);
+                causes.push(kind);
+                (None, Some((kind, error)))
+            }
+        };
+
+        report.tasks.push(ResolveRecord {
+            directory: preview
+                .task
+                .output_dir
+                .strip_prefix(output_dir)
+                .unwrap_or(&preview.task.output_dir)
+                .to_string_lossy()
+                .into_owned(),
+            url: preview.task.url,
+            resolver: preview.resolver,
+            resolved_url,
+            error_category: failure.as_ref().map(|(kind, _)| *kind),
+            error: failure.map(|(_, error)| error),
+        });
+    }
+
+    println!();
+    println!("=== By resolver ===");
+    for (resolver, count) in &by_resolver {
+        println!("  {resolver}: {count}");
+    }
+
+    outcome::print_by_cause(causes.iter().copied());
+
+    report.save(output_dir)?;
+    tracing::info!(
+        "dry run written to {}",
+        DryRunReport::path(output_dir).display()
+    );
+
+    Ok(exit_code(causes))
+}
@@ -0,0 +1,140 @@
+use std::time::Duration;
+
+use reqwest::StatusCode;
+use serde::{Deserialize, Serialize};
+
+/// Cause of a failed entry, used to group failures in the summary, `failed.log` and exit code
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorKind {
+    /// The file no longer exists (HTTP 404/410, removed from the hosting service)
+    DeadLink,
+    /// The file is private or behind a login (HTTP 401/403)
+    AuthRequired,
+    /// The hosting service or link type cannot be downloaded by bms-dl
+    UnsupportedHost,
+    /// The URL is malformed
+    InvalidUrl,
+    /// The page was fetched but contained no download link
+    NoDownloadLink,
+    /// The server returned an HTML page instead of an archive
+    HtmlInsteadOfArchive,
+    /// The server asked us to slow down (HTTP 429/503)
+    RateLimited,
+    /// The download succeeded but the table's chart is not in it
+    HashMismatch,
+    /// The archive could not be extracted
+    ExtractionFailed,
+    /// Connection errors, timeouts and HTTP 5xx
+    Network,
+    Other,
+}
+
+impl ErrorKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::DeadLink => "dead_link",
+            Self::AuthRequired => "auth_required",
+            Self::UnsupportedHost => "unsupported_host",
+            Self::InvalidUrl => "invalid_url",
+            Self::NoDownloadLink => "no_download_link",
+            Self::HtmlInsteadOfArchive => "html_instead_of_archive",
+            Self::RateLimited => "rate_limited",
+            Self::HashMismatch => "hash_mismatch",
+            Self::ExtractionFailed => "extraction_failed",
+            Self::Network => "network",
+            Self::Other => "other",
+        }
+    }
+
+    /// Whether retrying the same request may succeed.
+    /// Dead links, auth walls and content-type mismatches are deterministic.
+    pub fn is_retryable(self) -> bool {
+        matches!(self, Self::RateLimited | Self::Network | Self::Other)
+    }
+
+    /// Cause of an unsuccessful HTTP status
+    pub fn from_status(status: StatusCode) -> Self {
+        match status {
+            StatusCode::NOT_FOUND | StatusCode::GONE => Self::DeadLink,
+            StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => Self::AuthRequired,
+            StatusCode::TOO_MANY_REQUESTS | StatusCode::SERVICE_UNAVAILABLE => Self::RateLimited,
+            s if s.is_server_error() => Self::Network,
+            s if s.is_client_error() => Self::DeadLink,
+            _ => Self::Other,
+        }
+    }
+}
+
+impl std::fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Error with a known cause
+#[derive(Debug)]
+pub struct DownloadError {
+    pub kind: ErrorKind,
+    pub message: String,
+}
+
+impl std::fmt::Display for DownloadError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl std::error::Error for DownloadError {}
+
+/// Create an error with a known cause.
+pub fn error(kind: ErrorKind, message: impl Into<String>) -> anyhow::Error {
+    DownloadError {
+        kind,
+        message: message.into(),
+    }
+    .into()
+}
+
+/// The server asked us to slow down (HTTP 429 or 503)
+#[derive(Debug)]
+pub struct RateLimited {
+    pub status: StatusCode,
+    pub retry_after: Option<Duration>,
+}
+
+impl std::fmt::Display for RateLimited {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "server is rate limiting requests ({})", self.status)?;
+        if let Some(retry_after) = self.retry_after {
+            write!(f, ", retry after {}s", retry_after.as_secs())?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for RateLimited {}
+
+/// Determine the cause of an error from its typed cause, HTTP status or source error.
+pub fn classify(err: &anyhow::Error) -> ErrorKind {
+    for cause in err.chain() {
+        if let Some(e) = cause.downcast_ref::<DownloadError>() {
+            return e.kind;
+        }
+        if cause.downcast_ref::<RateLimited>().is_some() {
+            return ErrorKind::RateLimited;
+        }
+        if let Some(e) = cause.downcast_ref::<reqwest::Error>() {
+            return match e.status() {
+                Some(status) => ErrorKind::from_status(status),
+                None if e.is_builder() => ErrorKind::InvalidUrl,
+                None => ErrorKind::Network,
+            };
+        }
+        if cause.downcast_ref::<url::ParseError>().is_some() {
+            return ErrorKind::InvalidUrl;
+        }
+    }
+
+    ErrorKind::Other
+}
@@ -0,0 +1,160 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use anyhow::Result;
+
+use crate::cli::Args;
+use crate::download::{self, DownloadTask};
+use crate::error::ErrorKind;
+use crate::group::{EntryGroup, entry_hash};
+use crate::library::Library;
+use crate::outcome::{self, Failure, FinishedTask, TaskOutcome};
+use crate::pipeline::{download_options, owned_record};
+use crate::report::{EntryRecord, Outcome};
+use crate::{naming, normalize, verify};
+
+/// Folder of the owned base song when a group only lacks diff charts.
+pub fn diff_fill_folder(group: &EntryGroup, library: &Library) -> Option<PathBuf> {
+    let missing: Vec<_> = group
+        .entries
+        .iter()
+        .filter(|e| library.find(e).is_none())
+        .collect();
+    if missing.is_empty()
+        || !missing
+            .iter()
+            .all(|e| e.url_diff.as_deref().is_some_and(|u| !u.is_empty()))
+    {
+        return None;
+    }
+
+    library.find_folder(&group.entries)
+}
+
+/// Download the missing diffs of songs already in the player's library and copy their
+/// charts into the songs' folders.
+///
+/// Returns the report records of the groups' entries and the causes of failed entries.
+pub async fn fill_diffs(
+    client: &reqwest::Client,
+    args: &Args,
+    output_dir: &Path,
+    groups: &[(String, EntryGroup, PathBuf)],
+    library: &Library,
+) -> Result<(Vec<EntryRecord>, Vec<ErrorKind>)> {
+    let staging = output_dir.join(".fill");
+    let mut tasks = Vec::new();
+    for (dir_name, group, _) in groups {
+        let missing = group.entries.iter().filter(|e| library.find(e).is_none());
+        let mut diff_urls: Vec<String> = Vec::new();
+        for url in missing.filter_map(|e| e.url_diff.clone()) {
+            if !diff_urls.contains(&url) {
+                diff_urls.push(url);
+            }
+        }
+        for (i, diff_url) in diff_urls.into_iter().enumerate() {
+            tasks.push(DownloadTask {
+                url: diff_url,
+                output_dir: staging.join(dir_name),
+                fallback_name: format!("{}_diff{i}.zip", naming::leaf_name(dir_name)),
+                label: format!("[diff] {dir_name} #{i}"),
+            });
+        }
+    }
+
+    tracing::info!("{} diff tasks for songs in the library", tasks.len());
+    let options = download_options(args)?;
+    let cache = options.cache.clone();
+    let results = download::execute_downloads(client, tasks, options).await;
+
+    // Extract the diffs and copy their charts into the owned folders
+    let mut outcomes: HashMap<String, TaskOutcome> = HashMap::new();
+    for result in results {
+        let FinishedTask {
+            url,
+            archive,
+            mut outcome,
+            ..
+        } = result.into();
+        if let Some(path) = archive {
+            outcome.failure = normalize::extract_and_normalize(&path)
+                .err()
+                .map(|e| Failure {
+                    url: url.clone(),
+                    kind: ErrorKind::ExtractionFailed,
+                    message: format!("extraction failed for {}: {e}", path.display()),
+                });
+            if let Some(cache) = &cache
+                && outcome.failure.is_some()
+                && let Some(ref resolved_url) = outcome.resolved_url
+            {
+                cache.forget(resolved_url);
+            }
+        }
+        outcomes.insert(url, outcome);
+    }
+
+    let mut records = Vec::new();
+    let mut failures = Vec::new();
+    let mut filled = 0u32;
+    for (dir_name, group, folder) in groups {
+        let copied = normalize::copy_diff_files(&staging.join(dir_name), folder)?;
+        if copied > 0 {
+            tracing::info!("copied {copied} diff files into {}", folder.display());
+        }
+        let hashes = verify::hash_charts(folder)?;
+
+        for entry in &group.entries {
+            if library.find(entry).is_some() {
+                records.push(owned_record(entry, dir_name, library));
+                continue;
+            }
+
+            let url = entry.url_diff.clone().unwrap_or_default();
+            let task = outcomes.get(&url);
+            let chart = verify::find_entry(&hashes, entry);
+            let failure = match task.and_then(|t| t.failure.clone()) {
+                Some(failure) => Some(failure),
+                None if chart.is_none() && verify::has_hash(entry) => {
+                    let hash = entry_hash(entry).unwrap_or_default();
+                    Some(Failure {
+                        url: url.clone(),
+                        kind: ErrorKind::HashMismatch,
+                        message: format!("chart {hash} not found in {}", folder.display()),
+                    })
+                }
+                None => None,
+            };
+
+            let outcome = match (&failure, task) {
+                (None, _) => {
+                    filled += 1;
+                    Outcome::Downloaded
+                }
+                (Some(_), Some(t)) if t.skipped => Outcome::Skipped,
+                (Some(_), _) => Outcome::Failed,
+            };
+            records.push(EntryRecord {
+                resolved_url: task.and_then(|t| t.resolved_url.clone()),
+                outcome,
+                error_category: failure.as_ref().map(|f| f.kind),
+                error: failure.as_ref().map(|f| f.message.clone()),
+                bytes_downloaded: task.map_or(0, |t| t.bytes),
+                diff_files_copied: copied,
+                owned_path: chart.map(|c| c.path.to_string_lossy().into_owned()),
+                ..owned_record(entry, dir_name, library)
+            });
+            failures.extend(failure);
+        }
+    }
+    let _ = std::fs::remove_dir_all(&staging);
+
+    println!();
+    println!("=== Diff fill ===");
+    println!("  Filled: {filled}");
+    println!("  Failed: {}", failures.len());
+    outcome::print_by_cause(failures.iter().map(|f| f.kind));
+    outcome::print_failures("Diff fill failures", &failures);
+
+    Ok((records, failures.iter().map(|f| f.kind).collect()))
+}
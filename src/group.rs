@@ -0,0 +1,162 @@
+use std::collections::HashMap;
+use std::path::Path;
+
+use crate::download::DownloadTask;
+use crate::naming::{self, DirTemplate};
+use crate::overrides::Overrides;
+use crate::table::{SongEntry, TableHeader};
+
+/// Entries of one or more tables sharing an entry directory, and what to download for them
+#[derive(Default)]
+pub struct EntryGroup {
+    pub base_url: Option<String>,
+    pub diff_urls: Vec<String>,
+    pub entries: Vec<SongEntry>,
+    /// Directories (and their entries) of groups in other tables or levels that share this song
+    pub aliases: Vec<(String, Vec<SongEntry>)>,
+}
+
+impl EntryGroup {
+    /// Add an entry, taking the first base URL and collecting distinct diff URLs.
+    pub fn add(&mut self, entry: SongEntry) {
+        if self.base_url.is_none()
+            && let Some(ref url) = entry.url
+            && !url.is_empty()
+        {
+            self.base_url = Some(url.clone());
+        }
+
+        if let Some(ref diff_url) = entry.url_diff
+            && !diff_url.is_empty()
+            && !self.diff_urls.contains(diff_url)
+        {
+            self.diff_urls.push(diff_url.clone());
+        }
+
+        self.entries.push(entry);
+    }
+}
+
+/// Download tasks for one entry group: the base archive and, unless disabled, its diffs.
+pub fn group_tasks(
+    dir_name: &str,
+    group: &EntryGroup,
+    entry_dir: &Path,
+    no_diff: bool,
+) -> Vec<DownloadTask> {
+    let mut tasks = Vec::new();
+
+    // Base download
+    if let Some(ref base_url) = group.base_url {
+        tasks.push(DownloadTask {
+            url: base_url.clone(),
+            output_dir: entry_dir.to_path_buf(),
+            fallback_name: format!("{}.zip", naming::leaf_name(dir_name)),
+            label: format!("[base] {dir_name}"),
+        });
+    }
+
+    // Diff downloads
+    if !no_diff {
+        for (i, diff_url) in group.diff_urls.iter().enumerate() {
+            tasks.push(DownloadTask {
+                url: diff_url.clone(),
+                output_dir: entry_dir.to_path_buf(),
+                fallback_name: format!("{}_diff{i}.zip", naming::leaf_name(dir_name)),
+                label: format!("[diff] {dir_name} #{i}"),
+            });
+        }
+    }
+
+    tasks
+}
+
+/// Group entries of all tables by directory name.
+pub fn group_entries(
+    tables: &[(TableHeader, Vec<SongEntry>)],
+    template: &DirTemplate,
+    overrides: &Overrides,
+) -> Vec<(String, EntryGroup)> {
+    let mut groups: Vec<(String, EntryGroup)> = Vec::new();
+    let mut index: HashMap<String, usize> = HashMap::new();
+
+    for (header, entries) in tables {
+        for entry in entries {
+            let dir_name = template.render(entry, header);
+
+            let i = *index.entry(dir_name.clone()).or_insert_with(|| {
+                groups.push((dir_name, EntryGroup::default()));
+                groups.len() - 1
+            });
+            groups[i].1.add(entry.clone());
+        }
+    }
+    apply_overrides(&mut groups, overrides);
+
+    fold_aliases(groups)
+}
+
+/// Replace base URLs and add diff URLs of groups listed in the overrides file.
+pub fn apply_overrides(groups: &mut [(String, EntryGroup)], overrides: &Overrides) {
+    for (dir_name, group) in groups {
+        let Some(entry) = overrides.get(dir_name, &group.entries) else {
+            continue;
+        };
+
+        if let Some(url) = entry.base_url() {
+            tracing::info!("overriding base URL of {dir_name} with {url}");
+            group.base_url = Some(url);
+        }
+        for diff_url in &entry.diff_urls {
+            if !group.diff_urls.contains(diff_url) {
+                group.diff_urls.push(diff_url.clone());
+            }
+        }
+    }
+}
+
+/// Fold groups whose charts are already covered by an earlier group into aliases.
+///
+/// A group whose charts are all contained in an earlier group (e.g. the same song listed
+/// in another table) is not downloaded again; its directory becomes an alias of the
+/// earlier group and is populated by copying once the download completes.
+pub fn fold_aliases(groups: Vec<(String, EntryGroup)>) -> Vec<(String, EntryGroup)> {
+    let mut merged: Vec<(String, EntryGroup)> = Vec::new();
+    for (dir_name, group) in groups {
+        let keys: Vec<_> = group.entries.iter().filter_map(entry_hash).collect();
+        let primary = merged.iter_mut().find(|(_, p)| {
+            keys.len() == group.entries.len()
+                && keys.iter().all(|k| {
+                    p.entries
+                        .iter()
+                        .any(|e| entry_hash(e).as_deref() == Some(k.as_str()))
+                })
+        });
+
+        match primary {
+            Some((primary_name, primary)) => {
+                tracing::info!("{dir_name} shares its charts with {primary_name}");
+                if primary.base_url.is_none() {
+                    primary.base_url = group.base_url;
+                }
+                for diff_url in group.diff_urls {
+                    if !primary.diff_urls.contains(&diff_url) {
+                        primary.diff_urls.push(diff_url);
+                    }
+                }
+                primary.aliases.push((dir_name, group.entries));
+            }
+            None => merged.push((dir_name, group)),
+        }
+    }
+
+    merged
+}
+
+pub fn entry_hash(entry: &SongEntry) -> Option<String> {
+    [&entry.md5, &entry.sha256]
+        .into_iter()
+        .flatten()
+        .find(|h| !h.is_empty())
+        .map(|h| h.to_lowercase())
+}
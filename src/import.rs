@@ -0,0 +1,147 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+
+use anyhow::Result;
+
+use crate::error::ErrorKind;
+use crate::group::EntryGroup;
+use crate::manifest::{self, Manifest, ManifestDownload, ManifestEntry};
+use crate::outcome::exit_code;
+use crate::{archive, normalize, pipeline, verify};
+
+/// Extract archives obtained by hand, match their charts to table entries and place them
+/// in the entries' directories, merging diffs and filling alias directories as a download
+/// would.
+///
+/// Archives that cannot be extracted or match no entry make the run fail.
+pub fn import_archives(
+    output_dir: &Path,
+    import_dir: &Path,
+    groups: &[(String, EntryGroup)],
+    manifest: &mut Manifest,
+) -> Result<ExitCode> {
+    let mut archives: Vec<PathBuf> = std::fs::read_dir(import_dir)?
+        .filter_map(|e| e.ok())
+        .map(|e| e.path())
+        .filter(|p| p.is_file() && archive::ArchiveFormat::detect(p).is_ok())
+        .collect();
+    // Largest archives first, so that base archives are placed before their diffs
+    archives.sort_by_key(|p| std::cmp::Reverse(std::fs::metadata(p).map(|m| m.len()).unwrap_or(0)));
+
+    let staging = output_dir.join(".import");
+    let mut imported: HashMap<&str, Vec<ManifestDownload>> = HashMap::new();
+    let mut unmatched = Vec::new();
+
+    for archive_path in &archives {
+        let extract_dir = match archive::extract_archive(archive_path, &staging) {
+            Ok(dir) => dir,
+            Err(e) => {
+                tracing::warn!("extraction failed for {}: {e}", archive_path.display());
+                unmatched.push((
+                    archive_path,
+                    ErrorKind::ExtractionFailed,
+                    format!("extraction failed: {e}"),
+                ));
+                continue;
+            }
+        };
+        normalize::flatten_single_subdirs(&extract_dir)?;
+        let hashes = verify::hash_charts(&extract_dir)?;
+
+        let matched: Vec<_> = groups
+            .iter()
+            .filter(|(_, g)| {
+                g.entries
+                    .iter()
+                    .any(|e| verify::find_entry(&hashes, e).is_some())
+            })
+            .collect();
+        if matched.is_empty() {
+            unmatched.push((
+                archive_path,
+                ErrorKind::HashMismatch,
+                "no chart matches a table entry".to_string(),
+            ));
+        }
+
+        let archive_name = archive_path
+            .file_name()
+            .map(|n| n.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let url = url::Url::from_file_path(std::path::absolute(archive_path)?)
+            .map(String::from)
+            .unwrap_or_default();
+
+        for (dir_name, _) in matched {
+            let entry_dir = output_dir.join(dir_name);
+            if normalize::contains_bms_files(&entry_dir) {
+                let count = normalize::place_diff_files(
+                    &extract_dir,
+                    &normalize::song_folders(&entry_dir)?,
+                )?;
+                tracing::info!("merged {count} diff files from {archive_name} into {dir_name}");
+            } else {
+                normalize::copy_dir_all(&extract_dir, &entry_dir)?;
+                tracing::info!("imported {archive_name} into {dir_name}");
+            }
+            imported
+                .entry(dir_name)
+                .or_default()
+                .push(ManifestDownload {
+                    url: url.clone(),
+                    resolved_url: url.clone(),
+                    archive: archive_name.clone(),
+                });
+        }
+
+        let _ = std::fs::remove_dir_all(&extract_dir);
+    }
+    let _ = std::fs::remove_dir_all(&staging);
+    pipeline::populate_aliases(output_dir, groups);
+
+    // Record the entries whose charts are now present
+    let mut entry_count = 0u32;
+    let timestamp = manifest::now();
+    for (dir_name, group) in groups {
+        let Some(entry_downloads) = imported.get(dir_name.as_str()) else {
+            continue;
+        };
+        let hashes = verify::hash_charts(&output_dir.join(dir_name))?;
+        for entry in &group.entries {
+            if verify::find_entry(&hashes, entry).is_none() {
+                continue;
+            }
+            entry_count += 1;
+            manifest.insert(ManifestEntry {
+                md5: entry.md5.clone(),
+                sha256: entry.sha256.clone(),
+                title: entry.title.clone(),
+                level: entry.level.clone(),
+                directory: dir_name.clone(),
+                downloads: entry_downloads.clone(),
+                timestamp,
+            });
+        }
+    }
+    manifest.save(output_dir)?;
+
+    println!();
+    println!("=== Import ===");
+    println!("  Archives:  {}", archives.len());
+    println!(
+        "  Entries:   {entry_count} ({} directories)",
+        imported.len()
+    );
+    println!("  Unmatched: {}", unmatched.len());
+
+    if !unmatched.is_empty() {
+        println!();
+        println!("=== Unmatched ===");
+        for (path, kind, reason) in &unmatched {
+            println!("  [{kind}] {}\t{reason}", path.display());
+        }
+    }
+
+    Ok(exit_code(unmatched.iter().map(|(_, kind, _)| *kind)))
+}
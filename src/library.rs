@@ -0,0 +1,232 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use rusqlite::{Connection, OpenFlags};
+use serde::{Deserialize, Serialize};
+
+use crate::table::SongEntry;
+use crate::{bms, normalize, verify};
+
+const INDEX_FILE: &str = "library-index.json";
+
+/// Charts the player already owns, indexed by lowercase md5 and sha256
+#[derive(Debug, Default)]
+pub struct Library {
+    charts: HashMap<String, PathBuf>,
+    /// Chart paths by lowercase title and artist
+    songs: HashMap<(String, String), PathBuf>,
+}
+
+impl Library {
+    /// Add the charts indexed in beatoraja's `songdata.db`.
+    /// Relative paths are relative to the beatoraja directory containing the database.
+    pub fn add_beatoraja(&mut self, db_path: &Path) -> Result<()> {
+        let base = db_path.parent().unwrap_or(Path::new(""));
+        self.add_db(
+            db_path,
+            base,
+            "SELECT md5, sha256, path, title, artist FROM song",
+            "beatoraja",
+        )
+    }
+
+    /// Add the charts indexed in LR2's `song.db` (md5 only).
+    /// Relative paths are relative to the LR2 directory (`LR2files/Database/song.db`).
+    pub fn add_lr2(&mut self, db_path: &Path) -> Result<()> {
+        let base = db_path.ancestors().nth(3).unwrap_or(Path::new(""));
+        self.add_db(
+            db_path,
+            base,
+            "SELECT hash, NULL, path, title, artist FROM song",
+            "LR2",
+        )
+    }
+
+    /// Add the charts returned by a `(md5, sha256, path, title, artist)` query on a song database.
+    fn add_db(&mut self, db_path: &Path, base: &Path, query: &str, player: &str) -> Result<()> {
+        let conn = Connection::open_with_flags(db_path, OpenFlags::SQLITE_OPEN_READ_ONLY)
+            .with_context(|| format!("failed to open {}", db_path.display()))?;
+        let mut stmt = conn
+            .prepare(query)
+            .with_context(|| format!("{} is not a {player} song database", db_path.display()))?;
+        let rows = stmt.query_map([], |row| {
+            Ok((
+                row.get::<_, Option<String>>(0)?,
+                row.get::<_, Option<String>>(1)?,
+                row.get::<_, Option<String>>(2)?,
+                row.get::<_, Option<String>>(3)?,
+                row.get::<_, Option<String>>(4)?,
+            ))
+        })?;
+
+        let mut count = 0;
+        for row in rows {
+            let (md5, sha256, path, title, artist) = row?;
+            let path = db_path_to_local(base, &path.unwrap_or_default());
+            if let Some(key) = song_key(title.as_deref(), artist.as_deref()) {
+                self.songs.entry(key).or_insert_with(|| path.clone());
+            }
+            self.insert([md5, sha256], path);
+            count += 1;
+        }
+        tracing::info!("loaded {count} charts from {}", db_path.display());
+
+        Ok(())
+    }
+
+    /// Add every chart under the library roots, hashing only files that changed
+    /// since the index in `output_dir` was written.
+    pub fn add_roots(&mut self, roots: &[PathBuf], output_dir: &Path) -> Result<()> {
+        let index_path = output_dir.join(INDEX_FILE);
+        let previous = LibraryIndex::load(&index_path);
+        let mut index = LibraryIndex::default();
+        let mut hashed = 0;
+        let mut cached = 0;
+
+        for root in roots {
+            for path in normalize::find_bms_files(root)
+                .with_context(|| format!("failed to scan library {}", root.display()))?
+            {
+                let key = std::path::absolute(&path)?.to_string_lossy().into_owned();
+                // Overlapping or repeated roots list the same chart again
+                if index.charts.contains_key(&key) {
+                    continue;
+                }
+                let metadata = std::fs::metadata(&path)?;
+                let size = metadata.len();
+                let mtime = metadata
+                    .modified()
+                    .ok()
+                    .and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok())
+                    .map_or(0, |d| d.as_secs());
+
+                let chart = match previous.charts.get(&key) {
+                    Some(c) if c.size == size && c.mtime == mtime => {
+                        cached += 1;
+                        c.clone()
+                    }
+                    _ => {
+                        let hash = match verify::hash_file(&path) {
+                            Ok(hash) => hash,
+                            Err(e) => {
+                                tracing::warn!("failed to hash {}: {e}", path.display());
+                                continue;
+                            }
+                        };
+                        hashed += 1;
+                        let header = bms::parse(&path).unwrap_or_default();
+                        IndexedChart {
+                            size,
+                            mtime,
+                            md5: hash.md5,
+                            sha256: hash.sha256,
+                            title: header.title,
+                            artist: header.artist,
+                        }
+                    }
+                };
+
+                if let Some(key) = song_key(chart.title.as_deref(), chart.artist.as_deref()) {
+                    self.songs.entry(key).or_insert_with(|| path.clone());
+                }
+                self.insert([Some(chart.md5.clone()), Some(chart.sha256.clone())], path);
+                index.charts.insert(key, chart);
+            }
+        }
+
+        tracing::info!(
+            "indexed {} charts in library ({hashed} hashed, {cached} cached)",
+            index.charts.len()
+        );
+        index.save(&index_path)
+    }
+
+    fn insert(&mut self, hashes: [Option<String>; 2], path: PathBuf) {
+        for hash in hashes.into_iter().flatten() {
+            if !hash.is_empty() {
+                self.charts.insert(hash.to_lowercase(), path.clone());
+            }
+        }
+    }
+
+    /// Folder of an owned song that contains one of the entries' charts, or failing that,
+    /// a chart with the same title and artist.
+    pub fn find_folder(&self, entries: &[SongEntry]) -> Option<PathBuf> {
+        let chart = entries.iter().find_map(|e| self.find(e)).or_else(|| {
+            entries
+                .iter()
+                .filter_map(|e| song_key(e.title.as_deref(), e.artist.as_deref()))
+                .find_map(|key| self.songs.get(&key).map(PathBuf::as_path))
+        })?;
+        chart.parent().map(Path::to_path_buf)
+    }
+
+    /// Path of the owned chart matching a table entry's md5 or sha256.
+    pub fn find(&self, entry: &SongEntry) -> Option<&Path> {
+        [&entry.md5, &entry.sha256]
+            .into_iter()
+            .flatten()
+            .filter(|h| !h.is_empty())
+            .find_map(|h| self.charts.get(&h.to_lowercase()))
+            .map(PathBuf::as_path)
+    }
+}
+
+fn song_key(title: Option<&str>, artist: Option<&str>) -> Option<(String, String)> {
+    let title = title?.trim().to_lowercase();
+    if title.is_empty() {
+        return None;
+    }
+    Some((title, artist.unwrap_or("").trim().to_lowercase()))
+}
+
+/// Convert a path stored by the game (possibly Windows-style and relative to its
+/// install directory) to a local path.
+fn db_path_to_local(base: &Path, path: &str) -> PathBuf {
+    let path = if cfg!(windows) {
+        PathBuf::from(path)
+    } else {
+        PathBuf::from(path.replace('\\', "/"))
+    };
+    if path.is_relative() {
+        base.join(path)
+    } else {
+        path
+    }
+}
+
+/// Cached hashes of the charts under the library roots, keyed by path
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct LibraryIndex {
+    charts: HashMap<String, IndexedChart>,
+}
+
+/// A chart's hashes and header, valid while its size and modification time are unchanged
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct IndexedChart {
+    size: u64,
+    /// Seconds since the Unix epoch
+    mtime: u64,
+    md5: String,
+    sha256: String,
+    #[serde(default)]
+    title: Option<String>,
+    #[serde(default)]
+    artist: Option<String>,
+}
+
+impl LibraryIndex {
+    /// Load the index, starting over if it is missing or unreadable.
+    fn load(path: &Path) -> Self {
+        std::fs::read_to_string(path)
+            .ok()
+            .and_then(|text| serde_json::from_str(&text).ok())
+            .unwrap_or_default()
+    }
+
+    fn save(&self, path: &Path) -> Result<()> {
+        let text = serde_json::to_string(self)?;
+        std::fs::write(path, text).with_context(|| format!("failed to write {}", path.display()))
+    }
+}
@@ -1,25 +1,52 @@
 mod archive;
+mod bms;
 mod browser;
+mod cache;
+mod check;
 mod cli;
 mod download;
+mod dry_run;
+mod error;
+mod fill;
+mod group;
+mod import;
+mod library;
+mod manifest;
+mod naming;
 mod normalize;
+mod outcome;
+mod overrides;
+mod pipeline;
+mod report;
 mod resolve;
+mod retry;
+mod store;
+mod sync;
 mod table;
+mod throttle;
+mod verify;
 
-use std::collections::HashMap;
+use std::collections::HashSet;
 use std::path::{Path, PathBuf};
-use std::sync::Arc;
+use std::process::ExitCode;
 
 use anyhow::Result;
 use clap::Parser;
-use tokio::sync::Semaphore;
 
 use crate::cli::Args;
-use crate::download::{DownloadResult, DownloadTask};
+use crate::group::{group_entries, group_tasks};
+use crate::library::Library;
+use crate::manifest::Manifest;
+use crate::naming::sanitize_dir_name;
+use crate::outcome::exit_code;
+use crate::overrides::Overrides;
+use crate::pipeline::{link_songs, link_views, owned_record, run_downloads};
+use crate::report::{EntryRecord, Outcome, Report};
+use crate::store::Store;
 use crate::table::SongEntry;
 
 #[tokio::main]
-async fn main() -> Result<()> {
+async fn main() -> Result<ExitCode> {
     tracing_subscriber::fmt()
         .with_env_filter(
             tracing_subscriber::EnvFilter::from_default_env()
@@ -31,6 +58,26 @@ async fn main() -> Result<()> {
     let args = Args::parse();
     let output_dir = PathBuf::from(&args.output);
     tokio::fs::create_dir_all(&output_dir).await?;
+    let mut manifest = Manifest::load(&output_dir)?;
+    let overrides = match args.overrides {
+        Some(ref path) => Overrides::load(Path::new(path))?,
+        None => Overrides::default(),
+    };
+    let mut library = Library::default();
+    if let Some(ref db) = args.beatoraja_db {
+        library.add_beatoraja(Path::new(db))?;
+    }
+    if let Some(ref db) = args.lr2_db {
+        library.add_lr2(Path::new(db))?;
+    }
+    if !args.library.is_empty() {
+        let roots: Vec<_> = args.library.iter().map(PathBuf::from).collect();
+        library.add_roots(&roots, &output_dir)?;
+    }
+    let mut store = match args.store {
+        Some(ref dir) => Some(Store::open(Path::new(dir), args.link_mode, &output_dir)?),
+        None => None,
+    };
 
     let client = reqwest::Client::builder()
         .redirect(reqwest::redirect::Policy::limited(10))
@@ -39,313 +86,253 @@ async fn main() -> Result<()> {
         .cookie_store(true)
         .build()?;
 
-    // Phase 1: Fetch table
-    tracing::info!("fetching table from {}", args.table_url);
-    let (header, entries) = table::fetch_table(&client, &args.table_url).await?;
-    tracing::info!(
-        "table '{}' ({}): {} entries",
-        header.name,
-        header.symbol,
-        entries.len()
-    );
-
-    // Filter by level if specified
-    let entries: Vec<_> = if let Some(ref level) = args.level {
-        entries
-            .into_iter()
-            .filter(|e| e.level.as_deref() == Some(level))
-            .collect()
-    } else {
-        entries
-    };
-
-    tracing::info!("{} entries after filtering", entries.len());
-
-    // Phase 2: Group entries by base URL and generate download tasks
-    let groups = group_entries(&entries, &header.symbol);
-    let mut tasks = Vec::new();
-
-    for (dir_name, group) in &groups {
-        let entry_dir = output_dir.join(dir_name);
-
-        // Skip existing entries if requested, but clean up failed directories
-        if args.skip_existing && entry_dir.exists() {
-            extract_unprocessed_archives(&entry_dir);
-
-            if normalize::contains_bms_files(&entry_dir) {
-                tracing::info!("skipping download for existing: {dir_name}");
-                continue;
-            }
-
-            tracing::warn!("cleaning up failed directory: {dir_name}");
-            std::fs::remove_dir_all(&entry_dir)?;
-        }
+    if args.retry {
+        return retry::retry_failed(
+            &client,
+            &args,
+            &output_dir,
+            &overrides,
+            &mut manifest,
+            store.as_mut(),
+        )
+        .await;
+    }
 
-        // Base download
-        if let Some(ref base_url) = group.base_url {
-            tasks.push(DownloadTask {
-                url: base_url.clone(),
-                output_dir: entry_dir.clone(),
-                fallback_name: format!("{dir_name}.zip"),
-                label: format!("[base] {dir_name}"),
-            });
-        }
+    // Phase 1: Fetch tables
+    let mut table_urls = args.table_urls.clone();
+    if let Some(ref list) = args.table_list {
+        table_urls.extend(read_table_list(Path::new(list))?);
+    }
 
-        // Diff downloads
-        if !args.no_diff {
-            for (i, diff_url) in group.diff_urls.iter().enumerate() {
-                tasks.push(DownloadTask {
-                    url: diff_url.clone(),
-                    output_dir: entry_dir.clone(),
-                    fallback_name: format!("{dir_name}_diff{i}.zip"),
-                    label: format!("[diff] {dir_name} #{i}"),
-                });
+    let mut tables = Vec::new();
+    let mut snapshots = Vec::new();
+    // Keys of added and changed entries, whose previous downloads are replaced
+    let mut refresh: HashSet<String> = HashSet::new();
+
+    for table_url in &table_urls {
+        tracing::info!("fetching table from {table_url}");
+        let (header, entries) = table::fetch_table(&client, table_url).await?;
+        tracing::info!(
+            "table '{}' ({}): {} entries",
+            header.name,
+            header.symbol,
+            entries.len()
+        );
+
+        // In sync mode, only keep entries added or changed since the previous snapshot
+        let snapshot_path = sync::snapshot_path(&output_dir, &sanitize_dir_name(&header.name));
+        let previous = sync::load_snapshot(&snapshot_path)?;
+        snapshots.push((snapshot_path, previous.clone(), entries.clone()));
+        let entries = if args.sync {
+            match previous {
+                Some(previous) => {
+                    let changelog = sync::diff(&previous, &entries);
+                    changelog.print(&header.name);
+                    let pending = changelog.pending();
+                    refresh.extend(pending.iter().map(sync::entry_key));
+                    pending
+                }
+                None => {
+                    tracing::warn!(
+                        "no previous snapshot found for '{}', syncing all entries",
+                        header.name
+                    );
+                    entries
+                }
             }
-        }
+        } else {
+            entries
+        };
+
+        // Filter by level if specified
+        let entries: Vec<_> = if let Some(ref level) = args.level {
+            entries
+                .into_iter()
+                .filter(|e| e.level.as_deref() == Some(level))
+                .collect()
+        } else {
+            entries
+        };
+
+        tables.push((header, entries));
     }
 
-    tracing::info!("{} download tasks generated", tasks.len());
+    tracing::info!(
+        "{} entries after filtering",
+        tables.iter().map(|(_, e)| e.len()).sum::<usize>()
+    );
 
-    // Phase 3-4: Download with concurrency control
-    let download_start = std::time::Instant::now();
-    let results = download::execute_downloads(&client, tasks, args.jobs).await;
-    let download_duration = download_start.elapsed();
-
-    // Phase 5-6: Extract archives and normalize (parallel)
-    let mut success_count = 0u32;
-    let mut skip_count = 0u32;
-    let mut fail_count = 0u32;
-    let mut failed_entries = Vec::new();
-    let mut skipped_entries = Vec::new();
-
-    let extract_parallelism = std::thread::available_parallelism()
-        .map(|n| n.get())
-        .unwrap_or(4);
-    let extract_semaphore = Arc::new(Semaphore::new(extract_parallelism));
-    let mut extract_handles = Vec::new();
-
-    for result in results {
-        match result {
-            DownloadResult::Success { path } => {
-                success_count += 1;
-
-                let permit = extract_semaphore.clone().acquire_owned().await.unwrap();
-                extract_handles.push(tokio::task::spawn_blocking(move || {
-                    let _permit = permit;
-                    if let Err(e) = extract_and_normalize(&path) {
-                        tracing::warn!("extraction failed for {}: {e}", path.display());
-                    }
-                }));
-            }
-            DownloadResult::Skipped { url, reason } => {
-                skip_count += 1;
-                skipped_entries.push(format!("{url}\t{reason}"));
-            }
-            DownloadResult::Failed { url, error } => {
-                fail_count += 1;
-                failed_entries.push(format!("{url}\t{error}"));
-            }
+    // Phase 2: Group entries by base URL and generate download tasks
+    let groups = group_entries(&tables, &args.dir_template, &overrides);
+
+    // Skip songs whose charts are all in the player's library
+    let (groups, owned_groups): (Vec<_>, Vec<_>) = groups
+        .into_iter()
+        .partition(|(_, group)| !group.entries.iter().all(|e| library.find(e).is_some()));
+    let mut owned = Report::default();
+    for (dir_name, group) in &owned_groups {
+        tracing::info!("skipping download for owned: {dir_name}");
+        let aliases = group
+            .aliases
+            .iter()
+            .flat_map(|(alias, entries)| entries.iter().map(move |e| (alias, e)));
+        for (directory, entry) in group.entries.iter().map(|e| (dir_name, e)).chain(aliases) {
+            owned.entries.push(owned_record(entry, directory, &library));
         }
     }
 
-    for handle in extract_handles {
-        let _ = handle.await;
+    if let Some(ref import_dir) = args.import {
+        return import::import_archives(&output_dir, Path::new(import_dir), &groups, &mut manifest);
     }
 
-    // Apply diff normalization: copy diff BMS files into base directories
-    for dir_name in groups.keys() {
-        let entry_dir = output_dir.join(dir_name);
-        if !entry_dir.exists() {
-            continue;
-        }
-
-        // Find the main content directory (non-hidden)
-        let main_dirs: Vec<_> = std::fs::read_dir(&entry_dir)?
-            .filter_map(|e| e.ok())
-            .filter(|e| {
-                e.file_type().map(|t| t.is_dir()).unwrap_or(false)
-                    && !e.file_name().to_string_lossy().starts_with('.')
-            })
-            .collect();
-
-        // Find diff extracted directories
-        let diff_dirs: Vec<_> = std::fs::read_dir(&entry_dir)?
-            .filter_map(|e| e.ok())
-            .filter(|e| {
-                e.file_type().map(|t| t.is_dir()).unwrap_or(false)
-                    && e.file_name().to_string_lossy().starts_with('.')
-                    && e.file_name().to_string_lossy().ends_with("_extracted")
+    // Download only the missing diffs of songs already in the player's library
+    let mut causes = Vec::new();
+    let groups = if args.fill_diffs {
+        let (fill, groups): (Vec<_>, Vec<_>) = groups
+            .into_iter()
+            .map(|(dir_name, group)| {
+                let folder = fill::diff_fill_folder(&group, &library);
+                (dir_name, group, folder)
             })
+            .partition(|(_, _, folder)| folder.is_some());
+        let fill: Vec<_> = fill
+            .into_iter()
+            .map(|(dir_name, group, folder)| (dir_name, group, folder.unwrap()))
             .collect();
-
-        if let Some(main_dir) = main_dirs.first() {
-            for diff_dir in &diff_dirs {
-                let count =
-                    normalize::copy_diff_files(&diff_dir.path(), &main_dir.path()).unwrap_or(0);
-                if count > 0 {
-                    tracing::info!(
-                        "copied {count} diff files into {}",
-                        main_dir.path().display()
-                    );
-                }
-                // Clean up diff extracted directory
-                let _ = std::fs::remove_dir_all(diff_dir.path());
-            }
+        if !fill.is_empty() {
+            let (records, fill_causes) =
+                fill::fill_diffs(&client, &args, &output_dir, &fill, &library).await?;
+            owned.entries.extend(records);
+            causes = fill_causes;
         }
-    }
-
-    // Write failed log
-    if !failed_entries.is_empty() {
-        let failed_log = output_dir.join("failed.log");
-        tokio::fs::write(&failed_log, failed_entries.join("\n")).await?;
-        tracing::info!("failed entries written to {}", failed_log.display());
-    }
-
-    // Summary
-    let total_downloads = success_count + skip_count + fail_count;
-    let duration_secs = download_duration.as_secs_f64();
-    let rate = if duration_secs > 0.0 {
-        total_downloads as f64 / duration_secs
+        groups
+            .into_iter()
+            .map(|(dir_name, group, _)| (dir_name, group))
+            .collect()
     } else {
-        0.0
+        groups
     };
 
-    println!();
-    println!("=== Summary ===");
-    println!("  Success: {success_count}");
-    println!("  Skipped: {skip_count}");
-    println!("  Failed:  {fail_count}");
-    println!("  Duration: {duration_secs:.1}s ({rate:.1} downloads/s)");
-
-    if !failed_entries.is_empty() {
-        println!();
-        println!("=== Failed ===");
-        for entry in &failed_entries {
-            println!("  {entry}");
-        }
-    }
-
-    if !skipped_entries.is_empty() {
-        println!();
-        println!("=== Skipped ===");
-        for entry in &skipped_entries {
-            println!("  {entry}");
-        }
-    }
-
-    Ok(())
-}
-
-struct EntryGroup {
-    base_url: Option<String>,
-    diff_urls: Vec<String>,
-}
-
-fn group_entries(entries: &[SongEntry], symbol: &str) -> HashMap<String, EntryGroup> {
-    let mut groups: HashMap<String, EntryGroup> = HashMap::new();
-
-    for entry in entries {
-        let dir_name = make_dir_name(entry, symbol);
+    let mut tasks = Vec::new();
 
-        let group = groups.entry(dir_name).or_insert_with(|| EntryGroup {
-            base_url: None,
-            diff_urls: Vec::new(),
-        });
+    for (dir_name, group) in &groups {
+        let entry_dir = output_dir.join(dir_name);
 
-        if group.base_url.is_none()
-            && let Some(ref url) = entry.url
-            && !url.is_empty()
-        {
-            group.base_url = Some(url.clone());
-        }
+        let refreshed = group
+            .entries
+            .iter()
+            .any(|e| refresh.contains(&sync::entry_key(e)));
 
-        if let Some(ref diff_url) = entry.url_diff
-            && !diff_url.is_empty()
-            && !group.diff_urls.contains(diff_url)
+        // Link songs whose charts are all in the store instead of downloading them
+        if !refreshed
+            && let Some(ref store) = store
+            && let Some(folder) = store.find(&group.entries)
         {
-            group.diff_urls.push(diff_url.clone());
+            tracing::info!("linking {dir_name} from the store");
+            if !args.dry_run && !args.check {
+                link_views(store, &folder, &output_dir, dir_name, group);
+            }
+            continue;
         }
-    }
-
-    groups
-}
-
-fn make_dir_name(entry: &SongEntry, symbol: &str) -> String {
-    let level = entry.level.as_deref().unwrap_or("_");
-    let title = entry.title.as_deref().unwrap_or("unknown");
 
-    let name = format!("{symbol}{level}_{title}");
-    sanitize_dir_name(&name)
-}
-
-fn sanitize_dir_name(name: &str) -> String {
-    name.chars()
-        .map(|c| match c {
-            '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|' | '\0' => '_',
-            _ => c,
-        })
-        .collect::<String>()
-        .trim()
-        .to_string()
-}
+        // Skip existing entries if requested, but clean up failed directories
+        if !refreshed && args.skip_existing && entry_dir.exists() {
+            if group.entries.iter().all(|e| manifest.get(e).is_some()) {
+                tracing::info!("skipping download for entry recorded in manifest: {dir_name}");
+                continue;
+            }
 
-/// Scan a directory for unextracted archives and HTML junk files.
-/// Extracts valid archives and removes HTML files that were saved by mistake.
-fn extract_unprocessed_archives(dir: &Path) {
-    let Ok(entries) = std::fs::read_dir(dir) else {
-        return;
-    };
+            if !args.dry_run && !args.check {
+                normalize::extract_unprocessed_archives(&entry_dir);
+            }
 
-    for entry in entries.filter_map(|e| e.ok()) {
-        let path = entry.path();
-        if !path.is_file() {
-            continue;
-        }
+            if normalize::contains_bms_files(&entry_dir) {
+                tracing::info!("skipping download for existing: {dir_name}");
+                continue;
+            }
 
-        let fname = path.file_name().and_then(|n| n.to_str()).unwrap_or("");
-        if fname.starts_with('.') {
-            continue;
+            if !args.dry_run && !args.check {
+                tracing::warn!("cleaning up failed directory: {dir_name}");
+                normalize::clean_failed_dir(&entry_dir)?;
+            }
         }
 
-        // Remove HTML files that were saved by mistake
-        if archive::is_html(&path) {
-            tracing::warn!("removing HTML junk file: {}", path.display());
-            let _ = std::fs::remove_file(&path);
-            continue;
+        // An updated package replaces the previous download: extraction keeps existing
+        // files, so they are removed first
+        if refreshed
+            && !args.dry_run
+            && !args.check
+            && std::fs::symlink_metadata(&entry_dir).is_ok()
+        {
+            tracing::info!("clearing {dir_name} to download its updated package");
+            normalize::clean_failed_dir(&entry_dir)?;
         }
 
-        // Try to extract if it looks like an archive
-        if archive::ArchiveFormat::detect(&path).is_ok() {
-            tracing::info!("extracting unprocessed archive: {}", path.display());
-            if let Err(e) = extract_and_normalize(&path) {
-                tracing::warn!("extraction failed for {}: {e}", path.display());
-            }
-        }
+        tasks.extend(group_tasks(dir_name, group, &entry_dir, args.no_diff));
     }
-}
-
-fn extract_and_normalize(archive_path: &Path) -> Result<()> {
-    let parent = archive_path
-        .parent()
-        .ok_or_else(|| anyhow::anyhow!("archive has no parent directory"))?;
 
-    let extract_dir = archive::extract_archive(archive_path, parent)?;
+    tracing::info!("{} download tasks generated", tasks.len());
 
-    // Flatten single subdirectories
-    normalize::flatten_single_subdirs(&extract_dir)?;
+    if args.dry_run {
+        return dry_run::dry_run(&client, &args, &output_dir, tasks).await;
+    }
+    if args.check {
+        return check::check_links(&client, &args, &output_dir, tasks).await;
+    }
 
-    // Move extracted contents to parent
-    for entry in std::fs::read_dir(&extract_dir)? {
-        let entry = entry?;
-        let dest = parent.join(entry.file_name());
-        if !dest.exists() {
-            std::fs::rename(entry.path(), &dest)?;
-        }
+    // Keep the records of entries not processed in this run (other levels, unchanged
+    // entries in sync mode), replacing those of owned and filled directories
+    let mut previous = Report::load(&output_dir)?.unwrap_or_default();
+    let replaced: HashSet<&str> = owned.entries.iter().map(|r| r.directory.as_str()).collect();
+    previous
+        .entries
+        .retain(|r| !replaced.contains(r.directory.as_str()));
+    previous.entries.extend(owned.entries);
+
+    causes.extend(
+        run_downloads(
+            &client,
+            &args,
+            &output_dir,
+            &groups,
+            tasks,
+            &mut manifest,
+            previous,
+        )
+        .await?,
+    );
+    link_songs(&output_dir, &groups, store.as_mut())?;
+
+    // Advance the snapshots only past entries that are now present, so that failed and
+    // filtered entries are still pending on the next sync
+    let present: Vec<SongEntry> = Report::load(&output_dir)?
+        .unwrap_or_default()
+        .entries
+        .iter()
+        .filter(|r| {
+            matches!(
+                r.outcome,
+                Outcome::Downloaded | Outcome::Existing | Outcome::Owned | Outcome::NoUrl
+            )
+        })
+        .map(EntryRecord::song_entry)
+        .collect();
+    for (snapshot_path, previous, entries) in &snapshots {
+        let snapshot = sync::advance(previous.as_deref().unwrap_or_default(), entries, &present);
+        sync::save_snapshot(snapshot_path, &snapshot)?;
     }
 
-    // Clean up
-    let _ = std::fs::remove_dir_all(&extract_dir);
-    let _ = std::fs::remove_file(archive_path);
+    Ok(exit_code(causes))
+}
 
-    Ok(())
+/// Read table URLs from a list file (one per line, `#` starts a comment).
+fn read_table_list(path: &Path) -> Result<Vec<String>> {
+    let text = std::fs::read_to_string(path)
+        .map_err(|e| anyhow::anyhow!("failed to read table list {}: {e}", path.display()))?;
+    Ok(text
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(String::from)
+        .collect())
 }
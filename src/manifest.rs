@@ -0,0 +1,86 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::table::SongEntry;
+
+const MANIFEST_FILE: &str = "manifest.json";
+
+/// Persistent record of the table entries present in an output directory.
+/// Entries are keyed by chart md5 (or sha256 when the table omits md5).
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Manifest {
+    entries: HashMap<String, ManifestEntry>,
+}
+
+/// One downloaded and extracted table entry
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ManifestEntry {
+    pub md5: Option<String>,
+    pub sha256: Option<String>,
+    pub title: Option<String>,
+    pub level: Option<String>,
+    /// Extraction target directory, relative to the output directory
+    pub directory: String,
+    pub downloads: Vec<ManifestDownload>,
+    /// Seconds since the Unix epoch
+    pub timestamp: u64,
+}
+
+/// One archive that was downloaded into an entry directory
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ManifestDownload {
+    pub url: String,
+    pub resolved_url: String,
+    pub archive: String,
+}
+
+impl Manifest {
+    pub fn path(output_dir: &Path) -> PathBuf {
+        output_dir.join(MANIFEST_FILE)
+    }
+
+    /// Load the manifest from the output directory, or start an empty one.
+    pub fn load(output_dir: &Path) -> Result<Self> {
+        let path = Self::path(output_dir);
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+
+        let text = std::fs::read_to_string(&path)
+            .with_context(|| format!("failed to read {}", path.display()))?;
+        serde_json::from_str(&text).with_context(|| format!("failed to parse {}", path.display()))
+    }
+
+    pub fn save(&self, output_dir: &Path) -> Result<()> {
+        let path = Self::path(output_dir);
+        let text = serde_json::to_string_pretty(self)?;
+        std::fs::write(&path, text).with_context(|| format!("failed to write {}", path.display()))
+    }
+
+    /// Look up the record for a table entry by md5, then sha256.
+    pub fn get(&self, entry: &SongEntry) -> Option<&ManifestEntry> {
+        [&entry.md5, &entry.sha256]
+            .into_iter()
+            .flatten()
+            .find_map(|hash| self.entries.get(&hash.to_lowercase()))
+    }
+
+    /// Record a table entry. Entries without any hash cannot be tracked and are ignored.
+    pub fn insert(&mut self, record: ManifestEntry) {
+        let Some(key) = record.md5.as_ref().or(record.sha256.as_ref()) else {
+            return;
+        };
+        self.entries.insert(key.to_lowercase(), record);
+    }
+}
+
+/// Current time as seconds since the Unix epoch.
+pub fn now() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
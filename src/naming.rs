@@ -0,0 +1,217 @@
+use crate::table::{SongEntry, TableHeader};
+
+/// Entry directory naming template, e.g. `{table}/{level}/{artist} - {title}`.
+/// `/` separates nested directories.
+#[derive(Debug, Clone)]
+pub struct DirTemplate {
+    parts: Vec<Part>,
+}
+
+#[derive(Debug, Clone)]
+enum Part {
+    Literal(String),
+    Field(Field),
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Field {
+    Symbol,
+    Level,
+    Title,
+    Artist,
+    Md5,
+    Sha256,
+    Table,
+}
+
+impl Field {
+    fn from_name(name: &str) -> Option<Self> {
+        Some(match name {
+            "symbol" => Self::Symbol,
+            "level" => Self::Level,
+            "title" => Self::Title,
+            "artist" => Self::Artist,
+            "md5" => Self::Md5,
+            "sha256" => Self::Sha256,
+            "table" => Self::Table,
+            _ => return None,
+        })
+    }
+
+    fn value<'a>(self, entry: &'a SongEntry, header: &'a TableHeader) -> &'a str {
+        let value = match self {
+            Self::Symbol => Some(header.symbol.as_str()),
+            Self::Level => entry.level.as_deref(),
+            Self::Title => entry.title.as_deref(),
+            Self::Artist => entry.artist.as_deref(),
+            Self::Md5 => entry.md5.as_deref(),
+            Self::Sha256 => entry.sha256.as_deref(),
+            Self::Table => Some(header.name.as_str()),
+        };
+        match (value.filter(|v| !v.trim().is_empty()), self) {
+            (Some(value), _) => value,
+            (None, Self::Symbol) => "",
+            (None, Self::Title | Self::Artist | Self::Table) => "unknown",
+            (None, _) => "_",
+        }
+    }
+}
+
+/// Parse a `--dir-template` command-line value.
+pub fn parse_dir_template(value: &str) -> Result<DirTemplate, String> {
+    let mut parts = Vec::new();
+    let mut rest = value;
+
+    while let Some(start) = rest.find('{') {
+        if start > 0 {
+            parts.push(Part::Literal(rest[..start].to_string()));
+        }
+        let end = rest[start..]
+            .find('}')
+            .ok_or_else(|| format!("unclosed '{{' in template '{value}'"))?;
+        let name = &rest[start + 1..start + end];
+        let field = Field::from_name(name).ok_or_else(|| {
+            format!(
+                "unknown placeholder '{{{name}}}' (expected symbol, level, title, artist, md5, sha256 or table)"
+            )
+        })?;
+        parts.push(Part::Field(field));
+        rest = &rest[start + end + 1..];
+    }
+    if rest.contains('}') {
+        return Err(format!("unmatched '}}' in template '{value}'"));
+    }
+    if !rest.is_empty() {
+        parts.push(Part::Literal(rest.to_string()));
+    }
+    // Songs that render to the same directory are downloaded as one
+    if !parts
+        .iter()
+        .any(|p| matches!(p, Part::Field(Field::Title | Field::Md5 | Field::Sha256)))
+    {
+        return Err(
+            "template must contain {title}, {md5} or {sha256} to tell songs apart".to_string(),
+        );
+    }
+
+    Ok(DirTemplate { parts })
+}
+
+impl DirTemplate {
+    /// Directory of an entry relative to the output directory, with `/` separators.
+    /// Placeholder values never introduce separators of their own.
+    pub fn render(&self, entry: &SongEntry, header: &TableHeader) -> String {
+        let mut name = String::new();
+        for part in &self.parts {
+            match part {
+                Part::Literal(text) => name.push_str(text),
+                Part::Field(field) => {
+                    name.push_str(&field.value(entry, header).replace(['/', '\\'], "_"))
+                }
+            }
+        }
+
+        let name = name
+            .split('/')
+            .map(sanitize_dir_name)
+            .filter(|c| !c.is_empty())
+            .map(|c| {
+                if c == "." || c == ".." {
+                    "_".to_string()
+                } else {
+                    c
+                }
+            })
+            .collect::<Vec<_>>()
+            .join("/");
+        if name.is_empty() {
+            "unknown".to_string()
+        } else {
+            name
+        }
+    }
+}
+
+/// Last component of an entry directory, used to name its downloads.
+pub fn leaf_name(dir_name: &str) -> &str {
+    dir_name.rsplit('/').next().unwrap_or(dir_name)
+}
+
+pub fn sanitize_dir_name(name: &str) -> String {
+    name.chars()
+        .map(|c| match c {
+            '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|' | '\0' => '_',
+            _ => c,
+        })
+        .collect::<String>()
+        .trim()
+        .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header() -> TableHeader {
+        TableHeader {
+            name: "Satellite".to_string(),
+            symbol: "sl".to_string(),
+            data_url: String::new(),
+        }
+    }
+
+    fn entry(title: Option<&str>, level: Option<&str>) -> SongEntry {
+        SongEntry {
+            md5: Some("0123abcd".to_string()),
+            sha256: None,
+            title: title.map(String::from),
+            artist: Some("AC/DC".to_string()),
+            url: None,
+            url_diff: None,
+            level: level.map(String::from),
+        }
+    }
+
+    fn render(template: &str, entry: &SongEntry) -> String {
+        parse_dir_template(template)
+            .unwrap()
+            .render(entry, &header())
+    }
+
+    #[test]
+    fn default_template_matches_legacy_names() {
+        let template = "{symbol}{level}_{title}";
+        assert_eq!(
+            render(template, &entry(Some("Song"), Some("3"))),
+            "sl3_Song"
+        );
+        assert_eq!(render(template, &entry(None, None)), "sl__unknown");
+        assert_eq!(
+            render(template, &entry(Some("a/b: c?"), Some("1"))),
+            "sl1_a_b_ c_"
+        );
+    }
+
+    #[test]
+    fn nested_template() {
+        let template = "{table}/{level}/{artist} - {title}";
+        assert_eq!(
+            render(template, &entry(Some("Song"), Some("3"))),
+            "Satellite/3/AC_DC - Song"
+        );
+        assert_eq!(
+            render("{level}//{md5}", &entry(None, Some(".."))),
+            "_/0123abcd"
+        );
+        assert_eq!(leaf_name("Satellite/3/Song"), "Song");
+    }
+
+    #[test]
+    fn rejects_invalid_templates() {
+        assert!(parse_dir_template("{table}/{level}").is_err());
+        assert!(parse_dir_template("{title").is_err());
+        assert!(parse_dir_template("title}").is_err());
+        assert!(parse_dir_template("{name}").is_err());
+        assert!(parse_dir_template("{level}/{md5}").is_ok());
+    }
+}
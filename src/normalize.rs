@@ -1,8 +1,11 @@
+use std::collections::HashSet;
 use std::fs;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
 use anyhow::Result;
 
+use crate::{archive, bms, verify};
+
 const BMS_EXTENSIONS: &[&str] = &["bms", "bme", "bml", "bmson"];
 
 /// Flatten single-subdirectory nesting.
@@ -38,16 +41,27 @@ pub fn flatten_single_subdirs(dir: &Path) -> Result<()> {
     Ok(())
 }
 
+/// Check whether the path has a BMS chart extension.
+pub fn is_bms_file(path: &Path) -> bool {
+    path.extension()
+        .and_then(|e| e.to_str())
+        .is_some_and(|e| BMS_EXTENSIONS.contains(&e.to_lowercase().as_str()))
+}
+
 /// Check whether the directory contains any BMS files recursively.
 pub fn contains_bms_files(dir: &Path) -> bool {
     let Ok(files) = walkdir(dir) else {
         return false;
     };
-    files.iter().any(|path| {
-        path.extension()
-            .and_then(|e| e.to_str())
-            .is_some_and(|e| BMS_EXTENSIONS.contains(&e.to_lowercase().as_str()))
-    })
+    files.iter().any(|path| is_bms_file(path))
+}
+
+/// Recursively list all BMS files in a directory.
+pub fn find_bms_files(dir: &Path) -> Result<Vec<PathBuf>> {
+    Ok(walkdir(dir)?
+        .into_iter()
+        .filter(|path| is_bms_file(path))
+        .collect())
 }
 
 /// Copy diff files (.bms, .bme, .bml, .bmson) from src_dir to dest_dir.
@@ -58,28 +72,96 @@ pub fn copy_diff_files(src_dir: &Path, dest_dir: &Path) -> Result<u32> {
         return Ok(0);
     }
 
-    for entry in walkdir(src_dir)? {
-        let ext = entry
-            .extension()
-            .and_then(|e| e.to_str())
-            .map(|e| e.to_lowercase())
-            .unwrap_or_default();
+    for entry in find_bms_files(src_dir)? {
+        let filename = entry.file_name().unwrap();
+        let dest = dest_dir.join(filename);
+        if !dest.exists() {
+            fs::copy(&entry, &dest)?;
+            count += 1;
+        }
+    }
 
-        if BMS_EXTENSIONS.contains(&ext.as_str()) {
-            let filename = entry.file_name().unwrap();
-            let dest = dest_dir.join(filename);
-            if !dest.exists() {
-                fs::copy(&entry, &dest)?;
-                count += 1;
-            }
+    Ok(count)
+}
+
+/// Copy diff files from src_dir, placing each chart into the candidate folder that
+/// contains the most resource files it references, counted as `verify` does so that a
+/// `.wav` reference matches the `.ogg` or `.flac` a song ships.
+pub fn place_diff_files(src_dir: &Path, candidates: &[PathBuf]) -> Result<u32> {
+    let mut count = 0;
+
+    if !src_dir.exists() || candidates.is_empty() {
+        return Ok(0);
+    }
+
+    let contents: Vec<HashSet<String>> = candidates
+        .iter()
+        .map(|dir| resource_set(dir).unwrap_or_default())
+        .collect();
+
+    for chart in find_bms_files(src_dir)? {
+        let references = bms::parse(&chart).map(|c| c.resources).unwrap_or_default();
+        let (best, matches) = contents
+            .iter()
+            .map(|files| {
+                references
+                    .iter()
+                    .filter(|r| verify::resource_exists(files, r))
+                    .count()
+            })
+            .enumerate()
+            .max_by_key(|&(i, matches)| (matches, std::cmp::Reverse(i)))
+            .unwrap();
+        if matches == 0 {
+            tracing::warn!(
+                "no folder contains the resources referenced by {}, placing it in {}",
+                chart.display(),
+                candidates[best].display()
+            );
+        }
+
+        let dest = candidates[best].join(chart.file_name().unwrap());
+        if !dest.exists() {
+            fs::copy(&chart, &dest)?;
+            count += 1;
         }
     }
 
     Ok(count)
 }
 
+/// Lowercase paths of all files under a directory, relative to it with `/` separators.
+pub fn resource_set(dir: &Path) -> Result<HashSet<String>> {
+    Ok(walkdir(dir)?
+        .iter()
+        .filter_map(|path| path.strip_prefix(dir).ok())
+        .map(|path| path.to_string_lossy().replace('\\', "/").to_lowercase())
+        .collect())
+}
+
+/// Recursively copy a directory tree, skipping hidden entries.
+pub fn copy_dir_all(src_dir: &Path, dest_dir: &Path) -> Result<()> {
+    fs::create_dir_all(dest_dir)?;
+
+    for entry in fs::read_dir(src_dir)? {
+        let entry = entry?;
+        if entry.file_name().to_string_lossy().starts_with('.') {
+            continue;
+        }
+
+        let dest = dest_dir.join(entry.file_name());
+        if entry.file_type()?.is_dir() {
+            copy_dir_all(&entry.path(), &dest)?;
+        } else {
+            fs::copy(entry.path(), &dest)?;
+        }
+    }
+
+    Ok(())
+}
+
 /// Recursively list all files in a directory.
-fn walkdir(dir: &Path) -> Result<Vec<std::path::PathBuf>> {
+fn walkdir(dir: &Path) -> Result<Vec<PathBuf>> {
     let mut files = Vec::new();
 
     for entry in fs::read_dir(dir)? {
@@ -94,3 +176,154 @@ fn walkdir(dir: &Path) -> Result<Vec<std::path::PathBuf>> {
 
     Ok(files)
 }
+
+/// Scan a directory for unextracted archives and HTML junk files.
+/// Extracts valid archives and removes HTML files that were saved by mistake.
+pub fn extract_unprocessed_archives(dir: &Path) {
+    // Archives are never left in store views
+    if dir.is_symlink() {
+        return;
+    }
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return;
+    };
+
+    for entry in entries.filter_map(|e| e.ok()) {
+        let path = entry.path();
+        if !path.is_file() {
+            continue;
+        }
+
+        let fname = path.file_name().and_then(|n| n.to_str()).unwrap_or("");
+        if fname.starts_with('.') {
+            continue;
+        }
+
+        // Remove HTML files that were saved by mistake
+        if archive::is_html(&path) {
+            tracing::warn!("removing HTML junk file: {}", path.display());
+            let _ = std::fs::remove_file(&path);
+            continue;
+        }
+
+        // Try to extract if it looks like an archive
+        if archive::ArchiveFormat::detect(&path).is_ok() {
+            tracing::info!("extracting unprocessed archive: {}", path.display());
+            if let Err(e) = extract_and_normalize(&path) {
+                tracing::warn!("extraction failed for {}: {e}", path.display());
+            }
+        }
+    }
+}
+
+/// Remove the contents of a failed entry directory, keeping partial downloads
+/// (`.*.part` and their `.part.json` state) so they can be resumed.
+/// A symlink into the store is removed instead.
+pub fn clean_failed_dir(dir: &Path) -> Result<()> {
+    // A store view: remove the link, never the shared song it points to
+    if dir.is_symlink() {
+        std::fs::remove_file(dir).or_else(|_| std::fs::remove_dir(dir))?;
+        return Ok(());
+    }
+
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if name.starts_with('.') && (name.ends_with(".part") || name.ends_with(".part.json")) {
+            continue;
+        }
+
+        if entry.file_type()?.is_dir() {
+            std::fs::remove_dir_all(entry.path())?;
+        } else {
+            std::fs::remove_file(entry.path())?;
+        }
+    }
+
+    Ok(())
+}
+
+pub fn extract_and_normalize(archive_path: &Path) -> Result<()> {
+    let parent = archive_path
+        .parent()
+        .ok_or_else(|| anyhow::anyhow!("archive has no parent directory"))?;
+
+    // A diff URL pointing straight at a chart: treat it as an extracted diff so that it
+    // is placed next to the resources it references
+    if is_bms_file(archive_path) {
+        let stem = archive_path
+            .file_stem()
+            .map(|s| s.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let extract_dir = parent.join(format!(".{stem}_extracted"));
+        std::fs::create_dir_all(&extract_dir)?;
+        if let Some(name) = archive_path.file_name() {
+            std::fs::rename(archive_path, extract_dir.join(name))?;
+        }
+        return Ok(());
+    }
+
+    let extract_dir = archive::extract_archive(archive_path, parent)?;
+
+    // Flatten single subdirectories
+    flatten_single_subdirs(&extract_dir)?;
+
+    // Move extracted contents to parent
+    for entry in std::fs::read_dir(&extract_dir)? {
+        let entry = entry?;
+        let dest = parent.join(entry.file_name());
+        if !dest.exists() {
+            std::fs::rename(entry.path(), &dest)?;
+        }
+    }
+
+    // Clean up
+    let _ = std::fs::remove_dir_all(&extract_dir);
+    let _ = std::fs::remove_file(archive_path);
+
+    Ok(())
+}
+
+/// Folders of an entry directory a diff chart may belong to: the directory itself and
+/// its non-hidden subdirectories.
+pub fn song_folders(entry_dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut folders = vec![entry_dir.to_path_buf()];
+    for entry in std::fs::read_dir(entry_dir)? {
+        let entry = entry?;
+        if entry.file_type()?.is_dir() && !entry.file_name().to_string_lossy().starts_with('.') {
+            folders.push(entry.path());
+        }
+    }
+    Ok(folders)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn diff_charts_follow_substituted_extensions() {
+        let tmp = std::env::temp_dir().join(format!("bms-dl-diffs-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&tmp);
+        let (first, second, diffs) = (tmp.join("first"), tmp.join("second"), tmp.join("diffs"));
+        for dir in [&first, &second, &diffs] {
+            fs::create_dir_all(dir).unwrap();
+        }
+        fs::write(first.join("intro.wav"), "").unwrap();
+        // The song ships compressed keysounds while the diff references the .wav names
+        fs::write(second.join("kick.ogg"), "").unwrap();
+        fs::write(second.join("snare.flac"), "").unwrap();
+        fs::write(
+            diffs.join("another.bme"),
+            "#WAV01 kick.wav\n#WAV02 snare.wav\n#WAV03 intro.wav\n",
+        )
+        .unwrap();
+
+        let count = place_diff_files(&diffs, &[first.clone(), second.clone()]).unwrap();
+        assert_eq!(count, 1);
+        assert!(second.join("another.bme").exists());
+        assert!(!first.join("another.bme").exists());
+
+        fs::remove_dir_all(&tmp).unwrap();
+    }
+}
@@ -0,0 +1,147 @@
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::process::ExitCode;
+
+use crate::download::DownloadResult;
+use crate::error::ErrorKind;
+
+/// A failed or skipped download, or an entry that failed verification
+#[derive(Clone)]
+pub struct Failure {
+    pub url: String,
+    pub kind: ErrorKind,
+    pub message: String,
+}
+
+impl Failure {
+    pub fn log_line(&self) -> String {
+        format!("{}\t{}\t{}", self.url, self.kind, self.message)
+    }
+}
+
+/// Outcome of one download task, keyed by its output directory and URL
+#[derive(Default)]
+pub struct TaskOutcome {
+    pub resolved_url: Option<String>,
+    pub bytes: u64,
+    pub failure: Option<Failure>,
+    /// The URL could not be resolved, so nothing was attempted
+    pub skipped: bool,
+}
+
+/// A download task once it has run
+pub struct FinishedTask {
+    pub output_dir: PathBuf,
+    pub url: String,
+    /// The downloaded archive, still to be extracted
+    pub archive: Option<PathBuf>,
+    pub outcome: TaskOutcome,
+}
+
+impl From<DownloadResult> for FinishedTask {
+    fn from(result: DownloadResult) -> Self {
+        match result {
+            DownloadResult::Success {
+                path,
+                url,
+                resolved_url,
+                bytes,
+            } => Self {
+                output_dir: path.parent().map(PathBuf::from).unwrap_or_default(),
+                url,
+                archive: Some(path),
+                outcome: TaskOutcome {
+                    resolved_url: Some(resolved_url),
+                    bytes,
+                    ..Default::default()
+                },
+            },
+            DownloadResult::Skipped {
+                url,
+                output_dir,
+                kind,
+                reason,
+            } => Self {
+                outcome: TaskOutcome {
+                    failure: Some(Failure {
+                        url: url.clone(),
+                        kind,
+                        message: reason,
+                    }),
+                    skipped: true,
+                    ..Default::default()
+                },
+                output_dir,
+                url,
+                archive: None,
+            },
+            DownloadResult::Failed {
+                url,
+                output_dir,
+                resolved_url,
+                kind,
+                error,
+            } => Self {
+                outcome: TaskOutcome {
+                    resolved_url,
+                    failure: Some(Failure {
+                        url: url.clone(),
+                        kind,
+                        message: error,
+                    }),
+                    ..Default::default()
+                },
+                output_dir,
+                url,
+                archive: None,
+            },
+        }
+    }
+}
+
+/// Print the number of failures per cause, if there are any.
+pub fn print_by_cause(causes: impl IntoIterator<Item = ErrorKind>) {
+    let mut by_cause: BTreeMap<ErrorKind, usize> = BTreeMap::new();
+    for kind in causes {
+        *by_cause.entry(kind).or_default() += 1;
+    }
+
+    if !by_cause.is_empty() {
+        println!();
+        println!("=== By cause ===");
+        for (kind, count) in &by_cause {
+            println!("  {kind}: {count}");
+        }
+    }
+}
+
+/// Print a section listing failures with their cause, if there are any.
+pub fn print_failures(title: &str, failures: &[Failure]) {
+    if !failures.is_empty() {
+        println!();
+        println!("=== {title} ===");
+        for failure in failures {
+            println!("  [{}] {}\t{}", failure.kind, failure.url, failure.message);
+        }
+    }
+}
+
+/// Exit status for a finished run.
+///
+/// 0: everything succeeded; 2: some entries failed and retrying may help;
+/// 3: all failures are permanent (dead links, auth walls, mismatched charts, ...).
+pub fn exit_code(causes: impl IntoIterator<Item = ErrorKind>) -> ExitCode {
+    let mut failed = false;
+    for kind in causes {
+        if kind.is_retryable() {
+            return ExitCode::from(2);
+        }
+        failed = true;
+    }
+
+    if failed {
+        ExitCode::from(3)
+    } else {
+        ExitCode::SUCCESS
+    }
+}
@@ -0,0 +1,81 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use url::Url;
+
+use crate::table::SongEntry;
+
+/// Replacement download sources for a table entry
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct Override {
+    /// Base URL used instead of the table's URL
+    pub url: Option<String>,
+    /// Diff URLs downloaded in addition to the table's diff URLs
+    pub diff_urls: Vec<String>,
+    /// Local archive used instead of downloading the base, relative to the overrides file
+    pub archive: Option<PathBuf>,
+}
+
+impl Override {
+    /// The base URL to download, as a `file://` URL for local archives.
+    pub fn base_url(&self) -> Option<String> {
+        match &self.archive {
+            Some(path) => Url::from_file_path(path).ok().map(String::from),
+            None => self.url.clone(),
+        }
+    }
+}
+
+/// User-maintained overrides for entries with dead or unresolvable URLs.
+///
+/// The file is a JSON object keyed by chart md5/sha256 or by entry directory name.
+#[derive(Debug, Default)]
+pub struct Overrides {
+    entries: HashMap<String, Override>,
+}
+
+impl Overrides {
+    pub fn load(path: &Path) -> Result<Self> {
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("failed to read {}", path.display()))?;
+        let entries: HashMap<String, Override> = serde_json::from_str(&text)
+            .with_context(|| format!("failed to parse {}", path.display()))?;
+
+        let base = std::path::absolute(path)?
+            .parent()
+            .map(Path::to_path_buf)
+            .unwrap_or_default();
+        let entries = entries
+            .into_iter()
+            .map(|(key, mut entry)| {
+                entry.archive = entry.archive.map(|archive| base.join(archive));
+                let key = if is_hash(&key) {
+                    key.to_lowercase()
+                } else {
+                    key
+                };
+                (key, entry)
+            })
+            .collect();
+
+        Ok(Self { entries })
+    }
+
+    /// Find the override for an entry directory, by directory name or by any of its charts.
+    pub fn get(&self, dir_name: &str, entries: &[SongEntry]) -> Option<&Override> {
+        self.entries.get(dir_name).or_else(|| {
+            entries
+                .iter()
+                .flat_map(|e| [&e.md5, &e.sha256])
+                .flatten()
+                .find_map(|hash| self.entries.get(&hash.to_lowercase()))
+        })
+    }
+}
+
+fn is_hash(key: &str) -> bool {
+    matches!(key.len(), 32 | 64) && key.chars().all(|c| c.is_ascii_hexdigit())
+}
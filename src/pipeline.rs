@@ -0,0 +1,584 @@
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use anyhow::Result;
+use tokio::sync::Semaphore;
+
+use crate::cache::Cache;
+use crate::cli::Args;
+use crate::download::{self, DownloadOptions, DownloadTask};
+use crate::error::ErrorKind;
+use crate::group::{EntryGroup, entry_hash};
+use crate::library::Library;
+use crate::manifest::{self, Manifest, ManifestDownload, ManifestEntry};
+use crate::outcome::{self, Failure, FinishedTask, TaskOutcome};
+use crate::report::{EntryRecord, Outcome, Report};
+use crate::store::Store;
+use crate::table::SongEntry;
+use crate::throttle::{HostLimit, HostLimiter};
+use crate::{normalize, verify};
+
+pub fn download_options(args: &Args) -> Result<DownloadOptions> {
+    let cache = match args.cache_dir {
+        Some(ref dir) => Some(Arc::new(Cache::open(
+            Path::new(dir),
+            args.cache_size.saturating_mul(1024 * 1024),
+        )?)),
+        None => None,
+    };
+    Ok(DownloadOptions {
+        jobs: args.jobs,
+        retries: args.retries,
+        max_backoff: std::time::Duration::from_secs(args.max_backoff),
+        limiter: Arc::new(HostLimiter::new(
+            HostLimit {
+                concurrency: args.per_host_jobs.unwrap_or(args.jobs * 2).max(1),
+                delay: std::time::Duration::from_millis(args.host_delay),
+            },
+            args.host_limit.clone(),
+        )),
+        cache,
+    })
+}
+
+/// Results of checking the extracted charts against the table
+#[derive(Default)]
+struct Verification {
+    /// Entries whose chart was not found, keyed by entry directory and chart hash
+    mismatches: HashMap<(PathBuf, String), Failure>,
+    /// Chart path and missing resource files of entries with incomplete packages
+    missing: HashMap<(PathBuf, String), (PathBuf, Vec<String>)>,
+}
+
+/// Download the tasks, extract and normalize archives, merge diffs and verify charts,
+/// then write the manifest, `failed.log`, the JSON report and the summary.
+/// Returns the causes of failed and skipped downloads.
+///
+/// Records of `previous` for directories not downloaded in this run are kept in the report.
+pub async fn run_downloads(
+    client: &reqwest::Client,
+    args: &Args,
+    output_dir: &Path,
+    groups: &[(String, EntryGroup)],
+    tasks: Vec<DownloadTask>,
+    manifest: &mut Manifest,
+    previous: Report,
+) -> Result<Vec<ErrorKind>> {
+    let ran = !tasks.is_empty();
+    let mut task_counts: HashMap<PathBuf, usize> = HashMap::new();
+    for task in &tasks {
+        *task_counts.entry(task.output_dir.clone()).or_default() += 1;
+    }
+
+    // Phase 3-4: Download with concurrency control
+    let download_start = std::time::Instant::now();
+    let options = download_options(args)?;
+    let cache = options.cache.clone();
+    let results = download::execute_downloads(client, tasks, options).await;
+    let download_duration = download_start.elapsed();
+
+    // Phase 5-6: Extract archives and normalize (parallel)
+    let mut success_count = 0u32;
+    let mut skip_count = 0u32;
+    let mut fail_count = 0u32;
+    let mut failed_entries = Vec::new();
+    let mut skipped_entries = Vec::new();
+    let mut downloads: HashMap<PathBuf, Vec<ManifestDownload>> = HashMap::new();
+    let mut outcomes: HashMap<(PathBuf, String), TaskOutcome> = HashMap::new();
+    let mut extract_failed: HashSet<PathBuf> = HashSet::new();
+
+    let extract_parallelism = std::thread::available_parallelism()
+        .map(|n| n.get())
+        .unwrap_or(4);
+    let extract_semaphore = Arc::new(Semaphore::new(extract_parallelism));
+    let mut extract_handles = Vec::new();
+
+    for result in results {
+        let FinishedTask {
+            output_dir: entry_dir,
+            url,
+            archive,
+            outcome,
+        } = result.into();
+        match (&outcome.failure, archive) {
+            (None, Some(path)) => {
+                success_count += 1;
+                downloads
+                    .entry(entry_dir.clone())
+                    .or_default()
+                    .push(ManifestDownload {
+                        url: url.clone(),
+                        resolved_url: outcome.resolved_url.clone().unwrap_or_default(),
+                        archive: path
+                            .file_name()
+                            .map(|n| n.to_string_lossy().into_owned())
+                            .unwrap_or_default(),
+                    });
+
+                let permit = extract_semaphore.clone().acquire_owned().await.unwrap();
+                let (entry_dir, url) = (entry_dir.clone(), url.clone());
+                extract_handles.push(tokio::task::spawn_blocking(move || {
+                    let _permit = permit;
+                    match normalize::extract_and_normalize(&path) {
+                        Ok(()) => None,
+                        Err(e) => {
+                            tracing::warn!("extraction failed for {}: {e}", path.display());
+                            Some((
+                                entry_dir,
+                                Failure {
+                                    url,
+                                    kind: ErrorKind::ExtractionFailed,
+                                    message: format!(
+                                        "extraction failed for {}: {e}",
+                                        path.display()
+                                    ),
+                                },
+                            ))
+                        }
+                    }
+                }));
+            }
+            (Some(failure), _) if outcome.skipped => {
+                skip_count += 1;
+                skipped_entries.push(failure.clone());
+            }
+            (failure, _) => {
+                fail_count += 1;
+                failed_entries.extend(failure.clone());
+            }
+        }
+        outcomes.insert((entry_dir, url), outcome);
+    }
+
+    for handle in extract_handles {
+        if let Ok(Some((entry_dir, failure))) = handle.await {
+            extract_failed.insert(entry_dir.clone());
+            if let Some(outcome) = outcomes.get_mut(&(entry_dir, failure.url.clone())) {
+                outcome.failure = Some(failure.clone());
+                if let (Some(cache), Some(resolved_url)) = (&cache, &outcome.resolved_url) {
+                    cache.forget(resolved_url);
+                }
+            }
+            failed_entries.push(failure);
+        }
+    }
+
+    // Apply diff normalization: copy diff BMS files into base directories
+    let mut diff_copied: HashMap<PathBuf, u32> = HashMap::new();
+    for (dir_name, _) in groups {
+        let entry_dir = output_dir.join(dir_name);
+        if !entry_dir.exists() {
+            continue;
+        }
+
+        let candidates = normalize::song_folders(&entry_dir)?;
+
+        // Find diff extracted directories
+        let diff_dirs: Vec<_> = std::fs::read_dir(&entry_dir)?
+            .filter_map(|e| e.ok())
+            .filter(|e| {
+                e.file_type().map(|t| t.is_dir()).unwrap_or(false)
+                    && e.file_name().to_string_lossy().starts_with('.')
+                    && e.file_name().to_string_lossy().ends_with("_extracted")
+            })
+            .collect();
+
+        for diff_dir in &diff_dirs {
+            let count = normalize::place_diff_files(&diff_dir.path(), &candidates).unwrap_or(0);
+            if count > 0 {
+                tracing::info!("copied {count} diff files into {}", entry_dir.display());
+                *diff_copied.entry(entry_dir.clone()).or_default() += count;
+            }
+            // Clean up diff extracted directory
+            let _ = std::fs::remove_dir_all(diff_dir.path());
+        }
+    }
+
+    // Verify that each entry's chart is present and record verified entries in the manifest
+    let mut verification = Verification::default();
+    for (dir_name, group) in groups {
+        let entry_dir = output_dir.join(dir_name);
+        let Some(&expected) = task_counts.get(&entry_dir) else {
+            continue;
+        };
+        let Some(entry_downloads) = downloads.get(&entry_dir) else {
+            continue;
+        };
+        // Charts missing after a failed download or extraction are already reported as such
+        let complete = entry_downloads.len() == expected && !extract_failed.contains(&entry_dir);
+
+        let hashes = match verify::hash_charts(&entry_dir) {
+            Ok(hashes) => hashes,
+            Err(e) => {
+                tracing::warn!("failed to hash charts in {}: {e}", entry_dir.display());
+                continue;
+            }
+        };
+
+        let timestamp = manifest::now();
+        for entry in &group.entries {
+            let has_diff = entry.url_diff.as_deref().is_some_and(|u| !u.is_empty());
+            if !verify::has_hash(entry) || (args.no_diff && has_diff) {
+                continue;
+            }
+
+            let Some(chart) = verify::find_entry(&hashes, entry) else {
+                if !complete {
+                    continue;
+                }
+                let url = entry
+                    .url_diff
+                    .as_deref()
+                    .filter(|u| !u.is_empty())
+                    .or(group.base_url.as_deref())
+                    .unwrap_or("");
+                let hash = entry_hash(entry).unwrap_or_default();
+                tracing::warn!("hash mismatch in {dir_name}: chart {hash} not found");
+                let failure = Failure {
+                    url: url.to_string(),
+                    kind: ErrorKind::HashMismatch,
+                    message: format!("chart {hash} not found in {dir_name}"),
+                };
+                failed_entries.push(failure.clone());
+                // A cached archive lacking the chart would be restored again on every run
+                if let Some(cache) = &cache {
+                    for download in entry_downloads {
+                        cache.forget(&download.resolved_url);
+                    }
+                }
+                verification
+                    .mismatches
+                    .insert((entry_dir.clone(), hash), failure);
+                continue;
+            };
+            tracing::debug!("verified {}", chart.path.display());
+
+            match verify::missing_resources(&chart.path) {
+                Ok(missing) if !missing.is_empty() => {
+                    tracing::warn!(
+                        "{} references {} missing resources",
+                        chart.path.display(),
+                        missing.len()
+                    );
+                    let hash = entry_hash(entry).unwrap_or_default();
+                    verification
+                        .missing
+                        .insert((entry_dir.clone(), hash), (chart.path.clone(), missing));
+                }
+                Ok(_) => {}
+                Err(e) => tracing::warn!("failed to read {}: {e}", chart.path.display()),
+            }
+
+            manifest.insert(ManifestEntry {
+                md5: entry.md5.clone(),
+                sha256: entry.sha256.clone(),
+                title: entry.title.clone(),
+                level: entry.level.clone(),
+                directory: dir_name.clone(),
+                downloads: entry_downloads.clone(),
+                timestamp,
+            });
+        }
+    }
+    manifest.save(output_dir)?;
+
+    // Write failed log, removing a stale one from a previous run that downloaded something
+    let failed_log = output_dir.join("failed.log");
+    if !failed_entries.is_empty() {
+        let lines: Vec<_> = failed_entries.iter().map(Failure::log_line).collect();
+        tokio::fs::write(&failed_log, lines.join("\n")).await?;
+        tracing::info!("failed entries written to {}", failed_log.display());
+    } else if ran && failed_log.exists() {
+        tokio::fs::remove_file(&failed_log).await?;
+    }
+
+    // Write JSON report
+    let report = build_report(
+        output_dir,
+        groups,
+        &outcomes,
+        &verification,
+        &diff_copied,
+        args.no_diff,
+        previous,
+    );
+    report.save(output_dir)?;
+    tracing::info!("report written to {}", Report::path(output_dir).display());
+
+    // Summary
+    let total_downloads = success_count + skip_count + fail_count;
+    let duration_secs = download_duration.as_secs_f64();
+    let rate = if duration_secs > 0.0 {
+        total_downloads as f64 / duration_secs
+    } else {
+        0.0
+    };
+
+    println!();
+    println!("=== Summary ===");
+    println!("  Success: {success_count}");
+    println!("  Skipped: {skip_count}");
+    println!(
+        "  Owned:   {}",
+        report
+            .entries
+            .iter()
+            .filter(|r| r.outcome == Outcome::Owned)
+            .count()
+    );
+    println!("  Failed:  {}", failed_entries.len());
+    if !verification.missing.is_empty() {
+        println!("  Missing resources: {}", verification.missing.len());
+    }
+    println!("  Duration: {duration_secs:.1}s ({rate:.1} downloads/s)");
+
+    let causes: Vec<ErrorKind> = failed_entries
+        .iter()
+        .chain(&skipped_entries)
+        .map(|f| f.kind)
+        .collect();
+    outcome::print_by_cause(causes.iter().copied());
+    outcome::print_failures("Failed", &failed_entries);
+    outcome::print_failures("Skipped", &skipped_entries);
+
+    if !verification.missing.is_empty() {
+        println!();
+        println!("=== Missing resources (warning) ===");
+        let mut missing: Vec<_> = verification.missing.values().collect();
+        missing.sort();
+        for (chart, files) in missing {
+            println!("  {}\t{}", chart.display(), files.join(", "));
+        }
+    }
+
+    Ok(causes)
+}
+
+/// Populate entry and alias directories once downloads are done: with a store, move new
+/// songs into it and link them back, otherwise copy shared songs to their aliases.
+pub fn link_songs(
+    output_dir: &Path,
+    groups: &[(String, EntryGroup)],
+    store: Option<&mut Store>,
+) -> Result<()> {
+    let Some(store) = store else {
+        populate_aliases(output_dir, groups);
+        return Ok(());
+    };
+
+    for (dir_name, group) in groups {
+        let entry_dir = output_dir.join(dir_name);
+        if !normalize::contains_bms_files(&entry_dir) {
+            continue;
+        }
+
+        let folder = match store.find(&group.entries) {
+            Some(folder) => folder,
+            None => match store.adopt(&entry_dir) {
+                Ok(Some(folder)) => {
+                    tracing::info!("stored {dir_name} in {}", folder.display());
+                    folder
+                }
+                Ok(None) => continue,
+                Err(e) => {
+                    tracing::warn!("failed to move {dir_name} into the store: {e}");
+                    continue;
+                }
+            },
+        };
+        link_views(store, &folder, output_dir, dir_name, group);
+    }
+
+    store.save()
+}
+
+/// Link a stored song into its entry directory and alias directories where they lack charts.
+pub fn link_views(
+    store: &Store,
+    folder: &Path,
+    output_dir: &Path,
+    dir_name: &str,
+    group: &EntryGroup,
+) {
+    let views = std::iter::once(dir_name).chain(group.aliases.iter().map(|(a, _)| a.as_str()));
+    for view in views {
+        let view_dir = output_dir.join(view);
+        if normalize::contains_bms_files(&view_dir) {
+            continue;
+        }
+
+        // Replace a failed directory or a dangling link
+        let removed = match std::fs::symlink_metadata(&view_dir) {
+            Ok(meta) if meta.is_dir() => std::fs::remove_dir_all(&view_dir),
+            Ok(_) => std::fs::remove_file(&view_dir),
+            Err(_) => Ok(()),
+        };
+        let linked = removed
+            .map_err(anyhow::Error::from)
+            .and_then(|()| store.link(folder, &view_dir));
+        match linked {
+            Ok(()) => tracing::info!("linked {view} to {}", folder.display()),
+            Err(e) => tracing::warn!("failed to link {view}: {e}"),
+        }
+    }
+}
+
+/// Populate alias directories of songs shared between tables.
+pub fn populate_aliases(output_dir: &Path, groups: &[(String, EntryGroup)]) {
+    for (dir_name, group) in groups {
+        let entry_dir = output_dir.join(dir_name);
+        if group.aliases.is_empty() || !normalize::contains_bms_files(&entry_dir) {
+            continue;
+        }
+
+        for (alias, _) in &group.aliases {
+            let alias_dir = output_dir.join(alias);
+            if alias_dir.exists() {
+                continue;
+            }
+            match normalize::copy_dir_all(&entry_dir, &alias_dir) {
+                Ok(()) => tracing::info!("copied shared song {dir_name} to {alias}"),
+                Err(e) => tracing::warn!("failed to copy {dir_name} to {alias}: {e}"),
+            }
+        }
+    }
+}
+
+/// Build one report record per table entry, including entries of alias directories.
+fn build_report(
+    output_dir: &Path,
+    groups: &[(String, EntryGroup)],
+    outcomes: &HashMap<(PathBuf, String), TaskOutcome>,
+    verification: &Verification,
+    diff_copied: &HashMap<PathBuf, u32>,
+    no_diff: bool,
+    previous: Report,
+) -> Report {
+    let directories: HashSet<&str> = groups
+        .iter()
+        .flat_map(|(dir_name, group)| {
+            std::iter::once(dir_name.as_str()).chain(group.aliases.iter().map(|(a, _)| a.as_str()))
+        })
+        .collect();
+    let mut report = previous;
+    report
+        .entries
+        .retain(|r| !directories.contains(r.directory.as_str()));
+
+    for (dir_name, group) in groups {
+        let entry_dir = output_dir.join(dir_name);
+        let base = group
+            .base_url
+            .as_ref()
+            .and_then(|url| outcomes.get(&(entry_dir.clone(), url.clone())));
+        let copied = diff_copied.get(&entry_dir).copied().unwrap_or(0);
+
+        let mut records = Vec::new();
+        for entry in &group.entries {
+            let diff = entry
+                .url_diff
+                .as_ref()
+                .filter(|u| !u.is_empty() && !no_diff)
+                .and_then(|url| outcomes.get(&(entry_dir.clone(), url.clone())));
+            let tasks: Vec<&TaskOutcome> = base.into_iter().chain(diff).collect();
+            let key = (entry_dir.clone(), entry_hash(entry).unwrap_or_default());
+            let mismatch = verification.mismatches.get(&key);
+
+            let failure = tasks.iter().find_map(|t| t.failure.as_ref()).or(mismatch);
+            let outcome = match failure {
+                Some(_) if tasks.iter().all(|t| t.failure.is_none() || t.skipped) => {
+                    if mismatch.is_some() {
+                        Outcome::Failed
+                    } else {
+                        Outcome::Skipped
+                    }
+                }
+                Some(_) => Outcome::Failed,
+                None if !tasks.is_empty() => Outcome::Downloaded,
+                None if normalize::contains_bms_files(&entry_dir) => Outcome::Existing,
+                None => Outcome::NoUrl,
+            };
+
+            records.push(EntryRecord {
+                title: entry.title.clone(),
+                artist: entry.artist.clone(),
+                level: entry.level.clone(),
+                md5: entry.md5.clone(),
+                sha256: entry.sha256.clone(),
+                url: entry.url.clone(),
+                url_diff: entry.url_diff.clone(),
+                resolved_url: diff.or(base).and_then(|t| t.resolved_url.clone()),
+                outcome,
+                error_category: failure.map(|f| f.kind),
+                error: failure.map(|f| f.message.clone()),
+                bytes_downloaded: tasks.iter().map(|t| t.bytes).sum(),
+                directory: dir_name.clone(),
+                diff_files_copied: if diff.is_some() { copied } else { 0 },
+                owned_path: None,
+                missing_resources: verification
+                    .missing
+                    .get(&key)
+                    .map(|(_, missing)| missing.clone())
+                    .unwrap_or_default(),
+            });
+        }
+
+        // Entries of alias directories share the outcome of the matching chart
+        for (alias, entries) in &group.aliases {
+            for entry in entries {
+                let hash = entry_hash(entry);
+                let Some(primary) = records
+                    .iter()
+                    .find(|r| {
+                        hash.is_some()
+                            && [&r.md5, &r.sha256]
+                                .into_iter()
+                                .flatten()
+                                .any(|h| Some(h.to_lowercase()) == hash)
+                    })
+                    .cloned()
+                else {
+                    continue;
+                };
+                report.entries.push(EntryRecord {
+                    title: entry.title.clone(),
+                    artist: entry.artist.clone(),
+                    level: entry.level.clone(),
+                    url: entry.url.clone(),
+                    url_diff: entry.url_diff.clone(),
+                    directory: alias.clone(),
+                    ..primary
+                });
+            }
+        }
+
+        report.entries.extend(records);
+    }
+
+    report
+}
+
+/// Report record of a table entry that is already in the player's library.
+pub fn owned_record(entry: &SongEntry, directory: &str, library: &Library) -> EntryRecord {
+    EntryRecord {
+        title: entry.title.clone(),
+        artist: entry.artist.clone(),
+        level: entry.level.clone(),
+        md5: entry.md5.clone(),
+        sha256: entry.sha256.clone(),
+        url: entry.url.clone(),
+        url_diff: entry.url_diff.clone(),
+        resolved_url: None,
+        outcome: Outcome::Owned,
+        error_category: None,
+        error: None,
+        bytes_downloaded: 0,
+        directory: directory.to_string(),
+        diff_files_copied: 0,
+        owned_path: library
+            .find(entry)
+            .map(|p| p.to_string_lossy().into_owned()),
+        missing_resources: Vec::new(),
+    }
+}
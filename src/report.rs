@@ -0,0 +1,165 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::error::ErrorKind;
+use crate::resolve::Resolver;
+use crate::table::SongEntry;
+
+const REPORT_FILE: &str = "report.json";
+const DRY_RUN_FILE: &str = "dry-run.json";
+const CHECK_FILE: &str = "check.json";
+
+/// Machine-readable result of a run, one record per table entry
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Report {
+    pub entries: Vec<EntryRecord>,
+}
+
+/// Final state of a table entry after a run
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Outcome {
+    /// Downloaded and extracted in this run
+    Downloaded,
+    /// Already present in the output directory, not downloaded again
+    Existing,
+    /// URL could not be resolved to a download
+    Skipped,
+    /// Download, extraction or verification failed
+    Failed,
+    /// The entry has no URL to download
+    NoUrl,
+    /// The chart is already in the player's library, not downloaded
+    Owned,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EntryRecord {
+    pub title: Option<String>,
+    pub artist: Option<String>,
+    pub level: Option<String>,
+    pub md5: Option<String>,
+    pub sha256: Option<String>,
+    pub url: Option<String>,
+    pub url_diff: Option<String>,
+    pub resolved_url: Option<String>,
+    pub outcome: Outcome,
+    pub error_category: Option<ErrorKind>,
+    pub error: Option<String>,
+    pub bytes_downloaded: u64,
+    /// Entry directory, relative to the output directory
+    pub directory: String,
+    pub diff_files_copied: u32,
+    /// Path of the chart in the player's library, for owned entries
+    pub owned_path: Option<String>,
+    /// Keysounds and BGA files referenced by the chart that are not in the package
+    #[serde(default)]
+    pub missing_resources: Vec<String>,
+}
+
+impl Report {
+    pub fn path(output_dir: &Path) -> PathBuf {
+        output_dir.join(REPORT_FILE)
+    }
+
+    /// Load the report of the previous run, if any.
+    pub fn load(output_dir: &Path) -> Result<Option<Self>> {
+        let path = Self::path(output_dir);
+        if !path.exists() {
+            return Ok(None);
+        }
+
+        let text = std::fs::read_to_string(&path)
+            .with_context(|| format!("failed to read {}", path.display()))?;
+        serde_json::from_str(&text)
+            .map(Some)
+            .with_context(|| format!("failed to parse {}", path.display()))
+    }
+
+    pub fn save(&self, output_dir: &Path) -> Result<()> {
+        write_json(&Self::path(output_dir), self)
+    }
+}
+
+impl EntryRecord {
+    /// The table entry this record was created from
+    pub fn song_entry(&self) -> SongEntry {
+        SongEntry {
+            md5: self.md5.clone(),
+            sha256: self.sha256.clone(),
+            title: self.title.clone(),
+            artist: self.artist.clone(),
+            url: self.url.clone(),
+            url_diff: self.url_diff.clone(),
+            level: self.level.clone(),
+        }
+    }
+}
+
+/// Result of a dry run, one record per download task
+#[derive(Debug, Default, Serialize)]
+pub struct DryRunReport {
+    pub tasks: Vec<ResolveRecord>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ResolveRecord {
+    /// Entry directory, relative to the output directory
+    pub directory: String,
+    pub url: String,
+    /// Resolver branch that handled the URL, `None` if the URL is malformed
+    pub resolver: Option<Resolver>,
+    pub resolved_url: Option<String>,
+    pub error_category: Option<ErrorKind>,
+    pub error: Option<String>,
+}
+
+impl DryRunReport {
+    pub fn path(output_dir: &Path) -> PathBuf {
+        output_dir.join(DRY_RUN_FILE)
+    }
+
+    pub fn save(&self, output_dir: &Path) -> Result<()> {
+        write_json(&Self::path(output_dir), self)
+    }
+}
+
+/// Result of a link health check, one record per download task
+#[derive(Debug, Default, Serialize)]
+pub struct CheckReport {
+    pub tasks: Vec<CheckRecord>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct CheckRecord {
+    /// Entry directory, relative to the output directory
+    pub directory: String,
+    pub url: String,
+    pub resolver: Option<Resolver>,
+    pub resolved_url: Option<String>,
+    pub status: Option<u16>,
+    pub content_type: Option<String>,
+    pub content_length: Option<u64>,
+    /// Archive format detected from the first bytes (`zip`, `rar`, `7z`, `lzh`)
+    pub archive_format: Option<&'static str>,
+    pub healthy: bool,
+    pub error_category: Option<ErrorKind>,
+    pub error: Option<String>,
+}
+
+impl CheckReport {
+    pub fn path(output_dir: &Path) -> PathBuf {
+        output_dir.join(CHECK_FILE)
+    }
+
+    pub fn save(&self, output_dir: &Path) -> Result<()> {
+        write_json(&Self::path(output_dir), self)
+    }
+}
+
+fn write_json(path: &Path, value: &impl Serialize) -> Result<()> {
+    let text = serde_json::to_string_pretty(value)?;
+    std::fs::write(path, text).with_context(|| format!("failed to write {}", path.display()))
+}
@@ -1,8 +1,10 @@
 use anyhow::{Result, anyhow};
 use scraper::{Html, Selector};
+use serde::Serialize;
 use url::Url;
 
 use crate::browser;
+use crate::error::{self, ErrorKind};
 
 /// Resolved download URL with metadata
 #[derive(Debug, Clone)]
@@ -11,47 +13,65 @@ pub struct ResolvedUrl {
     pub original: String,
 }
 
-/// Resolve a URL to its actual download link.
-/// Some URLs point to HTML pages that contain the real download link.
-pub fn resolve_url<'a>(
-    client: &'a reqwest::Client,
-    raw_url: &'a str,
-) -> std::pin::Pin<Box<dyn std::future::Future<Output = Result<ResolvedUrl>> + Send + 'a>> {
-    let raw_url = raw_url.to_string();
-    let client = client.clone();
-    Box::pin(async move {
-        // Trim non-URL prefix (e.g. "東方https://..." → "https://...")
-        let raw_url = match raw_url.find("https://").or_else(|| raw_url.find("http://")) {
-            Some(pos) if pos > 0 => {
-                tracing::warn!("trimmed prefix from URL: {raw_url}");
-                raw_url[pos..].to_string()
-            }
-            _ => raw_url,
-        };
+/// Resolver branch of `resolve_url` that handles a URL
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Resolver {
+    /// Local archive from the overrides file
+    Local,
+    GoogleDrive,
+    Dropbox,
+    OneDrive,
+    /// 1drv.ms short link
+    OneDriveShort,
+    /// Event pages scraped with a headless browser fallback
+    ScrapeAndBrowser,
+    KBms,
+    VenueBmsSearch,
+    Mega,
+    /// URL with an archive or chart extension, downloaded as is
+    Direct,
+    /// Download link extracted from an HTML page
+    Generic,
+}
 
-        let parsed = Url::parse(&raw_url)?;
-        let host = parsed.host_str().unwrap_or("").to_string();
+impl Resolver {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Local => "local",
+            Self::GoogleDrive => "google_drive",
+            Self::Dropbox => "dropbox",
+            Self::OneDrive => "one_drive",
+            Self::OneDriveShort => "one_drive_short",
+            Self::ScrapeAndBrowser => "scrape_and_browser",
+            Self::KBms => "k_bms",
+            Self::VenueBmsSearch => "venue_bms_search",
+            Self::Mega => "mega",
+            Self::Direct => "direct",
+            Self::Generic => "generic",
+        }
+    }
 
-        match host.as_str() {
-            "drive.google.com" => resolve_google_drive(&raw_url),
-            "dropbox.com" | "www.dropbox.com" | "dl.dropboxusercontent.com" => {
-                resolve_dropbox(&raw_url)
-            }
-            "onedrive.live.com" | "www.onedrive.live.com" | "skydrive.live.com" => {
-                resolve_onedrive(&raw_url)
-            }
+    /// Pick the resolver for a URL by scheme, host and extension.
+    pub fn for_url(url: &Url) -> Self {
+        if url.scheme() == "file" {
+            return Self::Local;
+        }
+
+        match url.host_str().unwrap_or("") {
+            "drive.google.com" => Self::GoogleDrive,
+            "dropbox.com" | "www.dropbox.com" | "dl.dropboxusercontent.com" => Self::Dropbox,
+            "onedrive.live.com" | "www.onedrive.live.com" | "skydrive.live.com" => Self::OneDrive,
             "manbow.nothing.sh" | "event.yaruki0.net" | "yaruki0.sakura.ne.jp" => {
-                resolve_with_scrape_and_browser(&client, &raw_url, &host).await
+                Self::ScrapeAndBrowser
             }
-            "k-bms.com" | "www.k-bms.com" => resolve_kbms(&raw_url).await,
-            "venue.bmssearch.net" => resolve_venue_bmssearch(&client, &raw_url).await,
-            "mega.nz" => Err(anyhow!(
-                "mega.nz is not supported (encryption API required)"
-            )),
-            "1drv.ms" => resolve_1drv_ms(&client, &raw_url).await,
+            "k-bms.com" | "www.k-bms.com" => Self::KBms,
+            "venue.bmssearch.net" => Self::VenueBmsSearch,
+            "mega.nz" => Self::Mega,
+            "1drv.ms" => Self::OneDriveShort,
             _ => {
                 // Pass through URLs with archive extensions directly
-                let path_lower = parsed.path().to_lowercase();
+                let path_lower = url.path().to_lowercase();
                 let direct_download_extensions = [
                     ".zip", ".rar", ".7z", ".lzh", ".bms", ".bme", ".bml", ".pms",
                 ];
@@ -59,15 +79,69 @@ pub fn resolve_url<'a>(
                     .iter()
                     .any(|ext| path_lower.ends_with(ext))
                 {
-                    return Ok(ResolvedUrl {
-                        url: raw_url.clone(),
-                        original: raw_url,
-                    });
+                    Self::Direct
+                } else {
+                    Self::Generic
                 }
-                // Otherwise try to extract a download link from the page
-                resolve_generic(&client, &raw_url).await
             }
         }
+    }
+}
+
+/// Find the resolver branch that handles a table URL, or `None` if it is not a valid URL.
+pub fn resolver_for(raw_url: &str) -> Option<Resolver> {
+    Url::parse(&trim_url_prefix(raw_url))
+        .ok()
+        .map(|url| Resolver::for_url(&url))
+}
+
+/// Trim non-URL prefix (e.g. "東方https://..." → "https://...")
+fn trim_url_prefix(raw_url: &str) -> String {
+    match raw_url.find("https://").or_else(|| raw_url.find("http://")) {
+        Some(pos) if pos > 0 => raw_url[pos..].to_string(),
+        _ => raw_url.to_string(),
+    }
+}
+
+/// Resolve a URL to its actual download link.
+/// Some URLs point to HTML pages that contain the real download link.
+pub fn resolve_url<'a>(
+    client: &'a reqwest::Client,
+    raw_url: &'a str,
+) -> std::pin::Pin<Box<dyn std::future::Future<Output = Result<ResolvedUrl>> + Send + 'a>> {
+    let raw_url = raw_url.to_string();
+    let client = client.clone();
+    Box::pin(async move {
+        let trimmed = trim_url_prefix(&raw_url);
+        if trimmed != raw_url {
+            tracing::warn!("trimmed prefix from URL: {raw_url}");
+        }
+        let raw_url = trimmed;
+        let parsed = Url::parse(&raw_url)?;
+        let host = parsed.host_str().unwrap_or("").to_string();
+
+        match Resolver::for_url(&parsed) {
+            // Local archives from the overrides file are copied as is
+            Resolver::Local | Resolver::Direct => Ok(ResolvedUrl {
+                url: raw_url.clone(),
+                original: raw_url,
+            }),
+            Resolver::GoogleDrive => resolve_google_drive(&raw_url),
+            Resolver::Dropbox => resolve_dropbox(&raw_url),
+            Resolver::OneDrive => resolve_onedrive(&raw_url),
+            Resolver::ScrapeAndBrowser => {
+                resolve_with_scrape_and_browser(&client, &raw_url, &host).await
+            }
+            Resolver::KBms => resolve_kbms(&raw_url).await,
+            Resolver::VenueBmsSearch => resolve_venue_bmssearch(&client, &raw_url).await,
+            Resolver::Mega => Err(error::error(
+                ErrorKind::UnsupportedHost,
+                "mega.nz is not supported (encryption API required)",
+            )),
+            Resolver::OneDriveShort => resolve_1drv_ms(&client, &raw_url).await,
+            // Otherwise try to extract a download link from the page
+            Resolver::Generic => resolve_generic(&client, &raw_url).await,
+        }
     })
 }
 
@@ -77,8 +151,11 @@ fn resolve_google_drive(raw_url: &str) -> Result<ResolvedUrl> {
 
     // Google Drive folders cannot be downloaded directly
     if path.contains("/folders/") {
-        return Err(anyhow!(
-            "Google Drive folder URLs are not supported (only individual file links): {raw_url}"
+        return Err(error::error(
+            ErrorKind::UnsupportedHost,
+            format!(
+                "Google Drive folder URLs are not supported (only individual file links): {raw_url}"
+            ),
         ));
     }
 
@@ -96,7 +173,12 @@ fn resolve_google_drive(raw_url: &str) -> Result<ResolvedUrl> {
                 .find(|(k, _)| k == "id")
                 .map(|(_, v)| v.into_owned())
         })
-        .ok_or_else(|| anyhow!("failed to extract Google Drive file ID from {raw_url}"))?;
+        .ok_or_else(|| {
+            error::error(
+                ErrorKind::InvalidUrl,
+                format!("failed to extract Google Drive file ID from {raw_url}"),
+            )
+        })?;
 
     let download_url =
         format!("https://drive.google.com/uc?export=download&id={file_id}&confirm=t");
@@ -217,11 +299,12 @@ pub async fn find_download_from_candidates(
 /// Generic fallback resolver: fetch the page and try to find a download link.
 /// Used for unknown domains that might be event pages with download links.
 async fn resolve_generic(client: &reqwest::Client, raw_url: &str) -> Result<ResolvedUrl> {
-    let resp = client
-        .get(raw_url)
-        .send()
-        .await
-        .map_err(|e| anyhow!("failed to fetch {raw_url} for link extraction: {e}"))?;
+    let resp = client.get(raw_url).send().await.map_err(|e| {
+        error::error(
+            ErrorKind::Network,
+            format!("failed to fetch {raw_url} for link extraction: {e}"),
+        )
+    })?;
 
     let content_type = resp
         .headers()
@@ -254,7 +337,10 @@ async fn resolve_generic(client: &reqwest::Client, raw_url: &str) -> Result<Reso
     }
 
     // All attempts failed — no download link found on this page
-    Err(anyhow!("no download link found on page: {raw_url}"))
+    Err(error::error(
+        ErrorKind::NoDownloadLink,
+        format!("no download link found on page: {raw_url}"),
+    ))
 }
 
 async fn resolve_venue_bmssearch(client: &reqwest::Client, raw_url: &str) -> Result<ResolvedUrl> {
@@ -269,8 +355,9 @@ async fn resolve_venue_bmssearch(client: &reqwest::Client, raw_url: &str) -> Res
 
     match find_download_from_candidates(client, &candidates, raw_url).await {
         Some(result) => result,
-        None => Err(anyhow!(
-            "no download link found on venue.bmssearch.net page: {raw_url}"
+        None => Err(error::error(
+            ErrorKind::NoDownloadLink,
+            format!("no download link found on venue.bmssearch.net page: {raw_url}"),
         )),
     }
 }
@@ -296,8 +383,11 @@ async fn resolve_with_scrape_and_browser(
     );
     match browser::resolve_with_browser(raw_url).await {
         Ok(resolved) => Ok(resolved),
-        Err(e) => Err(anyhow!(
-            "no download link found on {site_name} (HTML scraping and browser both failed): {raw_url}: {e}"
+        Err(e) => Err(error::error(
+            ErrorKind::NoDownloadLink,
+            format!(
+                "no download link found on {site_name} (HTML scraping and browser both failed): {raw_url}: {e}"
+            ),
         )),
     }
 }
@@ -306,8 +396,11 @@ async fn resolve_with_scrape_and_browser(
 async fn resolve_kbms(raw_url: &str) -> Result<ResolvedUrl> {
     tracing::info!("k-bms.com requires JS execution, using headless browser: {raw_url}");
     browser::resolve_with_browser(raw_url).await.map_err(|e| {
-        anyhow!(
-            "failed to resolve k-bms.com via browser (JS security verification): {raw_url}: {e}"
+        error::error(
+            ErrorKind::NoDownloadLink,
+            format!(
+                "failed to resolve k-bms.com via browser (JS security verification): {raw_url}: {e}"
+            ),
         )
     })
 }
@@ -318,17 +411,23 @@ async fn resolve_1drv_ms(client: &reqwest::Client, raw_url: &str) -> Result<Reso
         .redirect(reqwest::redirect::Policy::none())
         .build()?;
 
-    let resp = no_redirect_client
-        .get(raw_url)
-        .send()
-        .await
-        .map_err(|e| anyhow!("failed to follow 1drv.ms redirect: {e}"))?;
+    let resp = no_redirect_client.get(raw_url).send().await.map_err(|e| {
+        error::error(
+            ErrorKind::Network,
+            format!("failed to follow 1drv.ms redirect: {e}"),
+        )
+    })?;
 
     let location = resp
         .headers()
         .get(reqwest::header::LOCATION)
         .and_then(|v| v.to_str().ok())
-        .ok_or_else(|| anyhow!("1drv.ms URL did not redirect: {raw_url}"))?
+        .ok_or_else(|| {
+            error::error(
+                ErrorKind::DeadLink,
+                format!("1drv.ms URL did not redirect: {raw_url}"),
+            )
+        })?
         .to_string();
 
     tracing::info!("1drv.ms redirected to: {location}");
@@ -345,7 +444,12 @@ fn resolve_onedrive(raw_url: &str) -> Result<ResolvedUrl> {
         .find(|(k, _)| k == "resid")
         .or_else(|| parsed.query_pairs().find(|(k, _)| k == "id"))
         .map(|(_, v)| v.into_owned())
-        .ok_or_else(|| anyhow!("failed to extract resource ID from OneDrive URL: {raw_url}"))?;
+        .ok_or_else(|| {
+            error::error(
+                ErrorKind::InvalidUrl,
+                format!("failed to extract resource ID from OneDrive URL: {raw_url}"),
+            )
+        })?;
 
     // Extract optional parameters
     let authkey = parsed
@@ -0,0 +1,101 @@
+use std::collections::HashSet;
+use std::path::Path;
+use std::process::ExitCode;
+
+use anyhow::Result;
+
+use crate::cli::Args;
+use crate::error::ErrorKind;
+use crate::group::{EntryGroup, apply_overrides, fold_aliases, group_tasks};
+use crate::manifest::Manifest;
+use crate::normalize;
+use crate::outcome::exit_code;
+use crate::overrides::Overrides;
+use crate::pipeline::{link_songs, run_downloads};
+use crate::report::{Outcome, Report};
+use crate::store::Store;
+
+/// Rebuild the download tasks of entries that failed or were skipped in the previous run
+/// and run them again.
+pub async fn retry_failed(
+    client: &reqwest::Client,
+    args: &Args,
+    output_dir: &Path,
+    overrides: &Overrides,
+    manifest: &mut Manifest,
+    store: Option<&mut Store>,
+) -> Result<ExitCode> {
+    let Some(previous) = Report::load(output_dir)? else {
+        anyhow::bail!(
+            "no report found in {}; run bms-dl on the table first",
+            output_dir.display()
+        );
+    };
+
+    // Rebuild the groups of the previous run from the directories of its entries
+    let mut groups: Vec<(String, EntryGroup)> = Vec::new();
+    for record in &previous.entries {
+        let i = match groups.iter().position(|(dir, _)| *dir == record.directory) {
+            Some(i) => i,
+            None => {
+                groups.push((record.directory.clone(), EntryGroup::default()));
+                groups.len() - 1
+            }
+        };
+        groups[i].1.add(record.song_entry());
+    }
+    apply_overrides(&mut groups, overrides);
+
+    // Failed entries, and skipped ones that may work now: transient resolve errors
+    // and entries given a replacement source in the overrides file
+    let failed: HashSet<&str> = previous
+        .entries
+        .iter()
+        .filter(|r| match r.outcome {
+            Outcome::Failed => true,
+            Outcome::Skipped => {
+                r.error_category.is_some_and(ErrorKind::is_retryable)
+                    || overrides
+                        .get(&r.directory, std::slice::from_ref(&r.song_entry()))
+                        .is_some()
+            }
+            _ => false,
+        })
+        .map(|r| r.directory.as_str())
+        .collect();
+    let groups: Vec<_> = fold_aliases(groups)
+        .into_iter()
+        .filter(|(dir_name, group)| {
+            failed.contains(dir_name.as_str())
+                || group
+                    .aliases
+                    .iter()
+                    .any(|(a, _)| failed.contains(a.as_str()))
+        })
+        .collect();
+
+    if groups.is_empty() {
+        println!("No failed or skipped entries to retry");
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    let mut tasks = Vec::new();
+    for (dir_name, group) in &groups {
+        let entry_dir = output_dir.join(dir_name);
+        if std::fs::symlink_metadata(&entry_dir).is_ok() {
+            normalize::clean_failed_dir(&entry_dir)?;
+        }
+        tasks.extend(group_tasks(dir_name, group, &entry_dir, args.no_diff));
+    }
+
+    tracing::info!(
+        "retrying {} entries ({} download tasks)",
+        groups.len(),
+        tasks.len()
+    );
+
+    let causes =
+        run_downloads(client, args, output_dir, &groups, tasks, manifest, previous).await?;
+    link_songs(output_dir, &groups, store)?;
+    Ok(exit_code(causes))
+}
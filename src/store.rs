@@ -0,0 +1,343 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+
+use crate::table::SongEntry;
+use crate::verify;
+
+const INDEX_FILE: &str = "index.json";
+
+/// How a song in the store is exposed in the output directory
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum LinkMode {
+    /// Mirror the song's folders and hardlink its files (same filesystem only)
+    Hardlink,
+    /// Symlink the entry directory to the song's folder
+    Symlink,
+}
+
+/// Content-addressed song store: each song is kept once, in a folder named after the
+/// sha256 of one of its charts, and linked into every entry directory listing it.
+pub struct Store {
+    root: PathBuf,
+    mode: LinkMode,
+    /// Song folder names by lowercase chart md5 and sha256
+    index: HashMap<String, String>,
+}
+
+impl Store {
+    /// Open the store, checking that songs can be linked into `output_dir` with `mode`.
+    pub fn open(root: &Path, mode: LinkMode, output_dir: &Path) -> Result<Self> {
+        fs::create_dir_all(root)
+            .with_context(|| format!("failed to create store {}", root.display()))?;
+        let root = std::path::absolute(root)?;
+        check_links(&root, mode, output_dir)?;
+        let index = fs::read_to_string(root.join(INDEX_FILE))
+            .ok()
+            .and_then(|text| serde_json::from_str::<StoreIndex>(&text).ok())
+            .map(|index| index.charts)
+            .unwrap_or_default();
+        let songs: HashSet<&String> = index.values().collect();
+        tracing::info!("store {} holds {} songs", root.display(), songs.len());
+
+        Ok(Self { root, mode, index })
+    }
+
+    pub fn save(&self) -> Result<()> {
+        let path = self.root.join(INDEX_FILE);
+        let index = StoreIndex {
+            charts: self.index.clone(),
+        };
+        fs::write(&path, serde_json::to_string(&index)?)
+            .with_context(|| format!("failed to write {}", path.display()))
+    }
+
+    /// Song folder holding every chart of the entries, if the store has them all.
+    pub fn find(&self, entries: &[SongEntry]) -> Option<PathBuf> {
+        let mut folder = None;
+        for entry in entries {
+            let name = [&entry.md5, &entry.sha256]
+                .into_iter()
+                .flatten()
+                .find_map(|h| self.index.get(&h.to_lowercase()))?;
+            if folder.is_some_and(|f| f != name) {
+                return None;
+            }
+            folder = Some(name);
+        }
+        folder
+            .map(|name| self.root.join(name))
+            .filter(|path| path.is_dir())
+    }
+
+    /// Move a downloaded entry directory into the store, merging it into the folder of a
+    /// song already stored, and link it back in place. A chart set whose files differ from
+    /// the stored ones gets a folder of its own. Returns the song folder, or `None` if the
+    /// directory has no charts.
+    pub fn adopt(&mut self, entry_dir: &Path) -> Result<Option<PathBuf>> {
+        if entry_dir.is_symlink() {
+            // Already a view: downloads went straight into the store
+            let folder = fs::read_link(entry_dir)?;
+            self.index_folder(&folder)?;
+            return Ok(Some(folder));
+        }
+
+        let hashes = verify::hash_charts(entry_dir)?;
+        let Some(first) = hashes.first() else {
+            return Ok(None);
+        };
+        let stored = hashes
+            .iter()
+            .flat_map(|h| [&h.md5, &h.sha256])
+            .find_map(|h| self.index.get(h))
+            .map(|name| self.root.join(name))
+            .filter(|path| path.is_dir());
+        let folder = match stored {
+            Some(folder) if !conflicts(entry_dir, &folder)? => folder,
+            _ => self.unused_folder(&first.sha256),
+        };
+        let created = !folder.exists();
+
+        // Keep the downloaded files until the view is in place
+        let staged = entry_dir.with_file_name(format!(
+            ".{}.adopting",
+            entry_dir.file_name().unwrap_or_default().to_string_lossy()
+        ));
+        fs::rename(entry_dir, &staged)?;
+        let result = copy_missing(&staged, &folder).and_then(|()| self.link(&folder, entry_dir));
+        if let Err(e) = result {
+            let _ = remove_view(entry_dir);
+            if created {
+                let _ = fs::remove_dir_all(&folder);
+            }
+            fs::rename(&staged, entry_dir)?;
+            return Err(e);
+        }
+        fs::remove_dir_all(&staged)?;
+        self.index_folder(&folder)?;
+
+        Ok(Some(folder))
+    }
+
+    /// Expose a song folder at `view`, which must not exist yet.
+    pub fn link(&self, folder: &Path, view: &Path) -> Result<()> {
+        if let Some(parent) = view.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        match self.mode {
+            LinkMode::Symlink => symlink_dir(folder, view)
+                .with_context(|| format!("failed to symlink {}", view.display())),
+            LinkMode::Hardlink => hardlink_tree(folder, view).with_context(|| {
+                format!(
+                    "failed to hardlink {} (the store and output directory must be on the same filesystem)",
+                    view.display()
+                )
+            }),
+        }
+    }
+
+    /// Folder named after a chart's sha256, suffixed if that name is taken.
+    fn unused_folder(&self, sha256: &str) -> PathBuf {
+        let mut folder = self.root.join(sha256);
+        let mut i = 1;
+        while folder.exists() {
+            folder = self.root.join(format!("{sha256}-{i}"));
+            i += 1;
+        }
+        folder
+    }
+
+    fn index_folder(&mut self, folder: &Path) -> Result<()> {
+        let name = folder
+            .file_name()
+            .map(|n| n.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        for hash in verify::hash_charts(folder)? {
+            self.index.insert(hash.md5, name.clone());
+            self.index.insert(hash.sha256, name.clone());
+        }
+        Ok(())
+    }
+}
+
+#[derive(Default, Serialize, Deserialize)]
+struct StoreIndex {
+    charts: HashMap<String, String>,
+}
+
+/// Check that a song in the store at `root` can be linked into `output_dir`.
+fn check_links(root: &Path, mode: LinkMode, output_dir: &Path) -> Result<()> {
+    fs::create_dir_all(output_dir)?;
+    let target = root.join(".link-check");
+    let link = output_dir.join(".store-link-check");
+    fs::write(&target, b"")?;
+    let _ = remove_view(&link);
+    let result = match mode {
+        LinkMode::Hardlink => fs::hard_link(&target, &link),
+        LinkMode::Symlink => symlink_dir(root, &link),
+    };
+    let _ = remove_view(&link);
+    let _ = fs::remove_file(&target);
+
+    result.with_context(|| match mode {
+        LinkMode::Hardlink => format!(
+            "cannot hardlink from store {} into {} (they must be on the same filesystem, or use --link-mode symlink)",
+            root.display(),
+            output_dir.display()
+        ),
+        LinkMode::Symlink => format!("cannot create symlinks in {}", output_dir.display()),
+    })
+}
+
+/// Whether a file of `src_dir` differs from the file of the same name in `dest_dir`.
+fn conflicts(src_dir: &Path, dest_dir: &Path) -> Result<bool> {
+    for entry in fs::read_dir(src_dir)? {
+        let entry = entry?;
+        if entry.file_name().to_string_lossy().starts_with('.') {
+            continue;
+        }
+
+        let dest = dest_dir.join(entry.file_name());
+        let differs = if entry.file_type()?.is_dir() {
+            dest.is_dir() && conflicts(&entry.path(), &dest)?
+        } else {
+            dest.exists() && fs::read(entry.path())? != fs::read(&dest)?
+        };
+        if differs {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+/// Add the files of `src_dir` that `dest_dir` lacks, e.g. newly downloaded diffs, linking
+/// them where possible.
+fn copy_missing(src_dir: &Path, dest_dir: &Path) -> Result<()> {
+    fs::create_dir_all(dest_dir)?;
+    for entry in fs::read_dir(src_dir)? {
+        let entry = entry?;
+        if entry.file_name().to_string_lossy().starts_with('.') {
+            continue;
+        }
+
+        let dest = dest_dir.join(entry.file_name());
+        if entry.file_type()?.is_dir() {
+            copy_missing(&entry.path(), &dest)?;
+        } else if !dest.exists() && fs::hard_link(entry.path(), &dest).is_err() {
+            fs::copy(entry.path(), &dest)?;
+        }
+    }
+    Ok(())
+}
+
+/// Remove a view left by a failed link: a symlink or a partial hardlinked tree.
+fn remove_view(view: &Path) -> std::io::Result<()> {
+    match fs::symlink_metadata(view) {
+        Ok(meta) if meta.is_dir() => fs::remove_dir_all(view),
+        Ok(_) => fs::remove_file(view).or_else(|_| fs::remove_dir(view)),
+        Err(_) => Ok(()),
+    }
+}
+
+fn hardlink_tree(src_dir: &Path, dest_dir: &Path) -> Result<()> {
+    fs::create_dir_all(dest_dir)?;
+    for entry in fs::read_dir(src_dir)? {
+        let entry = entry?;
+        let dest = dest_dir.join(entry.file_name());
+        if entry.file_type()?.is_dir() {
+            hardlink_tree(&entry.path(), &dest)?;
+        } else if !dest.exists() {
+            fs::hard_link(entry.path(), &dest)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+fn symlink_dir(target: &Path, link: &Path) -> std::io::Result<()> {
+    std::os::unix::fs::symlink(target, link)
+}
+
+#[cfg(windows)]
+fn symlink_dir(target: &Path, link: &Path) -> std::io::Result<()> {
+    std::os::windows::fs::symlink_dir(target, link)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::normalize;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("bms-dl-{name}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn write_song(dir: &Path, audio: &str) {
+        fs::create_dir_all(dir).unwrap();
+        fs::write(dir.join("a.bms"), "#TITLE Song\n#WAV01 a.wav\n").unwrap();
+        fs::write(dir.join("a.wav"), audio).unwrap();
+    }
+
+    #[test]
+    fn adopt_link_and_retry_keep_stored_songs() {
+        let tmp = temp_dir("store-symlink");
+        let output = tmp.join("out");
+        let mut store = Store::open(&tmp.join("store"), LinkMode::Symlink, &output).unwrap();
+
+        // A downloaded entry moves into the store and is linked back in place
+        let entry_dir = output.join("sl1_Song");
+        write_song(&entry_dir, "audio");
+        let folder = store.adopt(&entry_dir).unwrap().unwrap();
+        assert!(entry_dir.is_symlink());
+        assert_eq!(fs::read_to_string(folder.join("a.wav")).unwrap(), "audio");
+
+        // The same song in another table is linked to the same folder
+        let alias = output.join("st1_Song");
+        store.link(&folder, &alias).unwrap();
+        assert!(alias.join("a.bms").is_file());
+
+        // Retrying the entry removes its view, not the shared song
+        normalize::clean_failed_dir(&entry_dir).unwrap();
+        assert!(fs::symlink_metadata(&entry_dir).is_err());
+        assert!(folder.join("a.wav").is_file());
+        assert!(alias.join("a.wav").is_file());
+
+        // A download whose files differ from the stored song gets a folder of its own
+        write_song(&entry_dir, "remastered");
+        let other = store.adopt(&entry_dir).unwrap().unwrap();
+        assert_ne!(other, folder);
+        assert_eq!(fs::read_to_string(folder.join("a.wav")).unwrap(), "audio");
+        assert_eq!(
+            fs::read_to_string(entry_dir.join("a.wav")).unwrap(),
+            "remastered"
+        );
+
+        fs::remove_dir_all(&tmp).unwrap();
+    }
+
+    #[test]
+    fn hardlink_views_are_cleaned_without_touching_the_store() {
+        let tmp = temp_dir("store-hardlink");
+        let output = tmp.join("out");
+        let mut store = Store::open(&tmp.join("store"), LinkMode::Hardlink, &output).unwrap();
+
+        let entry_dir = output.join("sl1_Song");
+        write_song(&entry_dir, "audio");
+        let folder = store.adopt(&entry_dir).unwrap().unwrap();
+        assert!(!entry_dir.is_symlink());
+        assert!(entry_dir.join("a.wav").is_file());
+
+        normalize::clean_failed_dir(&entry_dir).unwrap();
+        assert!(!entry_dir.join("a.wav").exists());
+        assert_eq!(fs::read_to_string(folder.join("a.wav")).unwrap(), "audio");
+
+        fs::remove_dir_all(&tmp).unwrap();
+    }
+}
@@ -0,0 +1,224 @@
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+
+use crate::table::SongEntry;
+
+/// Differences between the previous snapshot of a table body and the current one
+#[derive(Debug, Default)]
+pub struct Changelog {
+    pub added: Vec<SongEntry>,
+    pub removed: Vec<SongEntry>,
+    /// Entries whose level changed, as (previous, current)
+    pub moved: Vec<(SongEntry, SongEntry)>,
+    /// Entries whose `url` or `url_diff` changed
+    pub changed: Vec<SongEntry>,
+}
+
+impl Changelog {
+    /// Entries that need to be downloaded: new ones and ones with changed URLs.
+    pub fn pending(&self) -> Vec<SongEntry> {
+        self.added.iter().chain(&self.changed).cloned().collect()
+    }
+
+    pub fn print(&self, table_name: &str) {
+        println!();
+        println!("=== Changelog: {table_name} ===");
+        println!(
+            "  Added: {}, Removed: {}, Moved: {}, Changed: {}",
+            self.added.len(),
+            self.removed.len(),
+            self.moved.len(),
+            self.changed.len()
+        );
+        for entry in &self.added {
+            println!("  + {}", describe(entry));
+        }
+        for entry in &self.removed {
+            println!("  - {}", describe(entry));
+        }
+        for (previous, current) in &self.moved {
+            println!(
+                "  ~ {} (level {} -> {})",
+                describe(current),
+                previous.level.as_deref().unwrap_or("?"),
+                current.level.as_deref().unwrap_or("?")
+            );
+        }
+        for entry in &self.changed {
+            println!("  * {} (URL changed)", describe(entry));
+        }
+    }
+}
+
+/// Path of the stored body snapshot for a table.
+pub fn snapshot_path(output_dir: &Path, table_name: &str) -> PathBuf {
+    output_dir.join(format!("{table_name}.snapshot.json"))
+}
+
+/// Load the previous snapshot, if one exists.
+pub fn load_snapshot(path: &Path) -> Result<Option<Vec<SongEntry>>> {
+    if !path.exists() {
+        return Ok(None);
+    }
+
+    let text = std::fs::read_to_string(path)
+        .with_context(|| format!("failed to read {}", path.display()))?;
+    let entries = serde_json::from_str(&text)
+        .with_context(|| format!("failed to parse {}", path.display()))?;
+    Ok(Some(entries))
+}
+
+pub fn save_snapshot(path: &Path, entries: &[SongEntry]) -> Result<()> {
+    let text = serde_json::to_string_pretty(entries)?;
+    std::fs::write(path, text).with_context(|| format!("failed to write {}", path.display()))
+}
+
+/// Snapshot to save after a run: current entries that are `present`, and the previous
+/// version of the others so that they are still reported as added or changed.
+pub fn advance(
+    previous: &[SongEntry],
+    current: &[SongEntry],
+    present: &[SongEntry],
+) -> Vec<SongEntry> {
+    let previous_by_key: HashMap<String, &SongEntry> =
+        previous.iter().map(|e| (entry_key(e), e)).collect();
+    let present: HashSet<String> = present.iter().map(entry_key).collect();
+
+    current
+        .iter()
+        .filter_map(|entry| {
+            let key = entry_key(entry);
+            if present.contains(&key) {
+                Some(entry.clone())
+            } else {
+                previous_by_key.get(&key).map(|e| (*e).clone())
+            }
+        })
+        .collect()
+}
+
+/// Compare two table bodies entry by entry.
+pub fn diff(previous: &[SongEntry], current: &[SongEntry]) -> Changelog {
+    let previous_by_key: HashMap<String, &SongEntry> =
+        previous.iter().map(|e| (entry_key(e), e)).collect();
+    let current_by_key: HashMap<String, &SongEntry> =
+        current.iter().map(|e| (entry_key(e), e)).collect();
+
+    let mut changelog = Changelog::default();
+
+    for entry in current {
+        let Some(old) = previous_by_key.get(&entry_key(entry)) else {
+            changelog.added.push(entry.clone());
+            continue;
+        };
+
+        if old.level != entry.level {
+            changelog.moved.push(((*old).clone(), entry.clone()));
+        }
+        if old.url != entry.url || old.url_diff != entry.url_diff {
+            changelog.changed.push(entry.clone());
+        }
+    }
+
+    for entry in previous {
+        if !current_by_key.contains_key(&entry_key(entry)) {
+            changelog.removed.push(entry.clone());
+        }
+    }
+
+    changelog
+}
+
+/// Identify an entry by md5, then sha256, then title and artist.
+pub fn entry_key(entry: &SongEntry) -> String {
+    [&entry.md5, &entry.sha256]
+        .into_iter()
+        .flatten()
+        .find(|h| !h.is_empty())
+        .map(|h| h.to_lowercase())
+        .unwrap_or_else(|| {
+            format!(
+                "{}\t{}",
+                entry.title.as_deref().unwrap_or(""),
+                entry.artist.as_deref().unwrap_or("")
+            )
+        })
+}
+
+fn describe(entry: &SongEntry) -> String {
+    format!(
+        "[{}] {}",
+        entry.level.as_deref().unwrap_or("?"),
+        entry.title.as_deref().unwrap_or("unknown")
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(md5: &str, level: &str, url: &str) -> SongEntry {
+        SongEntry {
+            md5: Some(md5.to_string()),
+            sha256: None,
+            title: Some(format!("title {md5}")),
+            artist: None,
+            url: Some(url.to_string()),
+            url_diff: None,
+            level: Some(level.to_string()),
+        }
+    }
+
+    #[test]
+    fn diff_classifies_entries() {
+        let previous = vec![
+            entry("aa", "1", "a.zip"),
+            entry("bb", "1", "b.zip"),
+            entry("cc", "1", "c.zip"),
+        ];
+        let current = vec![
+            entry("AA", "1", "a.zip"),
+            entry("bb", "2", "b.zip"),
+            entry("cc", "1", "c2.zip"),
+            entry("dd", "3", "d.zip"),
+        ];
+
+        let changelog = diff(&previous, &current);
+        let md5s = |entries: &[SongEntry]| -> Vec<String> {
+            entries.iter().filter_map(|e| e.md5.clone()).collect()
+        };
+        assert_eq!(md5s(&changelog.added), ["dd"]);
+        assert!(changelog.removed.is_empty());
+        assert_eq!(changelog.moved.len(), 1);
+        assert_eq!(changelog.moved[0].1.level.as_deref(), Some("2"));
+        assert_eq!(md5s(&changelog.changed), ["cc"]);
+        assert_eq!(md5s(&changelog.pending()), ["dd", "cc"]);
+
+        let changelog = diff(&current, &previous);
+        assert_eq!(md5s(&changelog.removed), ["dd"]);
+    }
+
+    #[test]
+    fn advance_keeps_pending_entries_pending() {
+        let previous = vec![entry("aa", "1", "a.zip"), entry("cc", "1", "c.zip")];
+        let current = vec![
+            entry("aa", "1", "a.zip"),
+            entry("bb", "2", "b.zip"),
+            entry("cc", "1", "c2.zip"),
+            entry("dd", "1", "d.zip"),
+        ];
+        let present = vec![entry("aa", "1", "a.zip"), entry("dd", "1", "d.zip")];
+
+        let snapshot = advance(&previous, &current, &present);
+        let changelog = diff(&snapshot, &current);
+        let pending: Vec<_> = changelog
+            .pending()
+            .into_iter()
+            .filter_map(|e| e.md5)
+            .collect();
+        // bb (filtered or failed) is still new, cc still has a changed URL
+        assert_eq!(pending, ["bb", "cc"]);
+    }
+}
@@ -1,6 +1,8 @@
+use std::path::{Path, PathBuf};
+
 use anyhow::{Context, Result, anyhow};
 use scraper::{Html, Selector};
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use url::Url;
 
 #[derive(Debug, Deserialize)]
@@ -10,81 +12,152 @@ pub struct TableHeader {
     pub data_url: String,
 }
 
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SongEntry {
-    #[allow(dead_code)]
     pub md5: Option<String>,
-    #[allow(dead_code)]
     pub sha256: Option<String>,
     pub title: Option<String>,
-    #[allow(dead_code)]
     pub artist: Option<String>,
     pub url: Option<String>,
     pub url_diff: Option<String>,
     pub level: Option<String>,
 }
 
-pub async fn fetch_table(
-    client: &reqwest::Client,
-    table_url: &str,
-) -> Result<(TableHeader, Vec<SongEntry>)> {
-    let table_url = Url::parse(table_url).context("invalid table URL")?;
-
-    // Fetch HTML and extract bmstable meta tag
-    let html_text = client
-        .get(table_url.as_str())
-        .send()
-        .await
-        .context("failed to fetch table HTML")?
-        .text()
-        .await
-        .context("failed to read table HTML body")?;
-
-    let document = Html::parse_document(&html_text);
-    let selector = Selector::parse(r#"meta[name="bmstable"]"#)
-        .map_err(|e| anyhow!("failed to parse selector: {e}"))?;
+/// Where a table document lives: on the web or on the local filesystem
+#[derive(Debug, Clone)]
+enum Location {
+    Remote(Url),
+    Local(PathBuf),
+}
 
-    let meta = document
-        .select(&selector)
-        .next()
-        .ok_or_else(|| anyhow!("bmstable meta tag not found"))?;
+impl Location {
+    fn parse(source: &str) -> Result<Self> {
+        if let Ok(url) = Url::parse(source) {
+            match url.scheme() {
+                "http" | "https" => return Ok(Self::Remote(url)),
+                "file" => {
+                    let path = url
+                        .to_file_path()
+                        .map_err(|_| anyhow!("invalid file URL: {source}"))?;
+                    return Ok(Self::Local(path));
+                }
+                _ => {}
+            }
+        }
+        Ok(Self::Local(PathBuf::from(source)))
+    }
 
-    let header_path = meta
-        .value()
-        .attr("content")
-        .ok_or_else(|| anyhow!("bmstable meta tag has no content attribute"))?;
+    /// Resolve a reference found in this document (absolute URL or relative path).
+    fn join(&self, reference: &str) -> Result<Self> {
+        if let Ok(url) = Url::parse(reference)
+            && matches!(url.scheme(), "http" | "https" | "file")
+        {
+            return Self::parse(reference);
+        }
+
+        match self {
+            Self::Remote(url) => {
+                Ok(Self::Remote(url.join(reference).with_context(|| {
+                    format!("failed to resolve {reference} against {url}")
+                })?))
+            }
+            Self::Local(path) => {
+                let base = path.parent().unwrap_or_else(|| Path::new("."));
+                Ok(Self::Local(base.join(reference)))
+            }
+        }
+    }
+
+    async fn read_text(&self, client: &reqwest::Client) -> Result<String> {
+        match self {
+            Self::Remote(url) => client
+                .get(url.as_str())
+                .send()
+                .await
+                .with_context(|| format!("failed to fetch {url}"))?
+                .text()
+                .await
+                .with_context(|| format!("failed to read body of {url}")),
+            Self::Local(path) => tokio::fs::read_to_string(path)
+                .await
+                .map(|text| text.trim_start_matches('\u{feff}').to_string())
+                .with_context(|| format!("failed to read {}", path.display())),
+        }
+    }
+
+    /// File name without extension, used to name tables that have no header.
+    fn stem(&self) -> String {
+        let stem = match self {
+            Self::Remote(url) => url
+                .path_segments()
+                .and_then(|mut s| s.next_back())
+                .map(|s| s.rsplit_once('.').map_or(s, |(stem, _)| stem).to_string()),
+            Self::Local(path) => path.file_stem().map(|s| s.to_string_lossy().into_owned()),
+        };
+        stem.filter(|s| !s.is_empty())
+            .unwrap_or_else(|| "table".to_string())
+    }
+}
 
-    // Resolve header URL relative to table URL
-    let header_url = table_url
-        .join(header_path)
-        .context("failed to resolve header URL")?;
+impl std::fmt::Display for Location {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Remote(url) => write!(f, "{url}"),
+            Self::Local(path) => write!(f, "{}", path.display()),
+        }
+    }
+}
+
+/// Load a table from a table HTML page, header.json, or body.json.
+///
+/// The source may be an HTTP(S) URL or a local path. A body.json on its own has no
+/// header, so the table is named after the file and uses an empty symbol.
+pub async fn fetch_table(
+    client: &reqwest::Client,
+    source: &str,
+) -> Result<(TableHeader, Vec<SongEntry>)> {
+    let location = Location::parse(source)?;
+    let text = location.read_text(client).await?;
 
-    tracing::info!("fetching header from {header_url}");
+    let (header_location, header_text) = match text.trim_start().chars().next() {
+        // body.json: no header available
+        Some('[') => {
+            let entries: Vec<SongEntry> = serde_json::from_str(&text)
+                .with_context(|| format!("failed to parse body.json from {location}"))?;
+            let header = TableHeader {
+                name: location.stem(),
+                symbol: String::new(),
+                data_url: location.to_string(),
+            };
+            tracing::info!(
+                "loaded {} entries from body '{}' (no header)",
+                entries.len(),
+                header.name
+            );
+            return Ok((header, entries));
+        }
+        // header.json: skip the meta tag scrape
+        Some('{') => (location, text),
+        // Table HTML: extract bmstable meta tag
+        _ => {
+            let header_path = find_header_path(&text)?;
+            let header_location = location.join(&header_path)?;
+            tracing::info!("fetching header from {header_location}");
+            let header_text = header_location.read_text(client).await?;
+            (header_location, header_text)
+        }
+    };
 
-    let header: TableHeader = client
-        .get(header_url.as_str())
-        .send()
-        .await
-        .context("failed to fetch header.json")?
-        .json()
-        .await
-        .context("failed to parse header.json")?;
+    let header: TableHeader = serde_json::from_str(&header_text)
+        .with_context(|| format!("failed to parse header.json from {header_location}"))?;
 
     // Resolve data URL relative to header URL
-    let data_url = header_url
-        .join(&header.data_url)
-        .context("failed to resolve data URL")?;
+    let data_location = header_location.join(&header.data_url)?;
 
-    tracing::info!("fetching body from {data_url}");
+    tracing::info!("fetching body from {data_location}");
 
-    let entries: Vec<SongEntry> = client
-        .get(data_url.as_str())
-        .send()
-        .await
-        .context("failed to fetch body.json")?
-        .json()
-        .await
-        .context("failed to parse body.json")?;
+    let entries: Vec<SongEntry> = serde_json::from_str(&data_location.read_text(client).await?)
+        .with_context(|| format!("failed to parse body.json from {data_location}"))?;
 
     tracing::info!(
         "loaded {} entries from table '{}'",
@@ -94,3 +167,19 @@ pub async fn fetch_table(
 
     Ok((header, entries))
 }
+
+fn find_header_path(html_text: &str) -> Result<String> {
+    let document = Html::parse_document(html_text);
+    let selector = Selector::parse(r#"meta[name="bmstable"]"#)
+        .map_err(|e| anyhow!("failed to parse selector: {e}"))?;
+
+    let meta = document
+        .select(&selector)
+        .next()
+        .ok_or_else(|| anyhow!("bmstable meta tag not found"))?;
+
+    meta.value()
+        .attr("content")
+        .map(String::from)
+        .ok_or_else(|| anyhow!("bmstable meta tag has no content attribute"))
+}
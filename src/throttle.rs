@@ -0,0 +1,147 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tokio::time::Instant;
+
+/// Concurrency cap and minimum spacing between requests for one host
+#[derive(Debug, Clone, Copy)]
+pub struct HostLimit {
+    pub concurrency: usize,
+    pub delay: Duration,
+}
+
+/// Parse a `DOMAIN=JOBS[:DELAY_MS]` command-line value.
+pub fn parse_host_limit(value: &str) -> Result<(String, HostLimit), String> {
+    let (domain, limit) = value
+        .split_once('=')
+        .ok_or_else(|| format!("expected DOMAIN=JOBS[:DELAY_MS], got '{value}'"))?;
+    let (jobs, delay) = match limit.split_once(':') {
+        Some((jobs, delay)) => (jobs, Some(delay)),
+        None => (limit, None),
+    };
+
+    let concurrency: usize = jobs
+        .parse()
+        .map_err(|_| format!("invalid job count '{jobs}'"))?;
+    if concurrency == 0 {
+        return Err("job count must be at least 1".to_string());
+    }
+    let delay_ms: u64 = match delay {
+        Some(delay) => delay
+            .parse()
+            .map_err(|_| format!("invalid delay '{delay}'"))?,
+        None => 0,
+    };
+
+    Ok((
+        domain.trim().to_lowercase(),
+        HostLimit {
+            concurrency,
+            delay: Duration::from_millis(delay_ms),
+        },
+    ))
+}
+
+struct HostState {
+    semaphore: Arc<Semaphore>,
+    delay: Duration,
+    next_request: Mutex<Instant>,
+}
+
+/// Limits concurrent requests and request rate per host.
+///
+/// Hosts without an explicit limit share the default limit, each with its own counter.
+pub struct HostLimiter {
+    default: HostLimit,
+    overrides: HashMap<String, HostLimit>,
+    hosts: Mutex<HashMap<String, Arc<HostState>>>,
+}
+
+/// Held while a request to a host is in flight
+pub struct HostPermit {
+    _permit: OwnedSemaphorePermit,
+}
+
+impl HostLimiter {
+    pub fn new(
+        default: HostLimit,
+        overrides: impl IntoIterator<Item = (String, HostLimit)>,
+    ) -> Self {
+        Self {
+            default,
+            overrides: overrides.into_iter().collect(),
+            hosts: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Wait for a free slot on the URL's host. Call [`HostLimiter::throttle`] right
+    /// before each request sent under the permit.
+    pub async fn acquire(&self, url: &str) -> HostPermit {
+        let state = self.state(&host_of(url));
+        let permit = state.semaphore.clone().acquire_owned().await.unwrap();
+
+        HostPermit { _permit: permit }
+    }
+
+    /// Wait for the host's politeness delay (or backoff) and reserve its next request slot.
+    pub async fn throttle(&self, url: &str) {
+        wait_turn(&self.state(&host_of(url))).await;
+    }
+
+    /// Hold back every request to the URL's host for at least `duration`.
+    pub fn pause(&self, url: &str, duration: Duration) {
+        let state = self.state(&host_of(url));
+        let mut next_request = state.next_request.lock().unwrap();
+        *next_request = (*next_request).max(Instant::now() + duration);
+    }
+
+    fn state(&self, host: &str) -> Arc<HostState> {
+        let mut hosts = self.hosts.lock().unwrap();
+        hosts
+            .entry(host.to_string())
+            .or_insert_with(|| {
+                let limit = self.limit_for(host);
+                Arc::new(HostState {
+                    semaphore: Arc::new(Semaphore::new(limit.concurrency)),
+                    delay: limit.delay,
+                    next_request: Mutex::new(Instant::now()),
+                })
+            })
+            .clone()
+    }
+
+    /// Find the limit for a host, matching configured domains and their subdomains.
+    fn limit_for(&self, host: &str) -> HostLimit {
+        self.overrides
+            .iter()
+            .filter(|(domain, _)| host == *domain || host.ends_with(&format!(".{domain}")))
+            .max_by_key(|(domain, _)| domain.len())
+            .map(|(_, limit)| *limit)
+            .unwrap_or(self.default)
+    }
+}
+
+/// Sleep until the host's next request slot, then reserve it.
+async fn wait_turn(state: &HostState) {
+    loop {
+        let next = {
+            let mut next_request = state.next_request.lock().unwrap();
+            let now = Instant::now();
+            if *next_request <= now {
+                *next_request = now + state.delay;
+                return;
+            }
+            *next_request
+        };
+        tokio::time::sleep_until(next).await;
+    }
+}
+
+fn host_of(url: &str) -> String {
+    url::Url::parse(url)
+        .ok()
+        .and_then(|u| u.host_str().map(str::to_lowercase))
+        .unwrap_or_default()
+}
@@ -0,0 +1,115 @@
+use std::collections::HashSet;
+use std::fs;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+use anyhow::Result;
+use md5::{Digest, Md5};
+use sha2::Sha256;
+
+use crate::bms::{self, ResourceKind};
+use crate::normalize;
+use crate::table::SongEntry;
+
+/// Extensions BMS players try in place of a missing sound file
+const SOUND_EXTENSIONS: &[&str] = &["wav", "ogg", "flac"];
+/// Extensions BMS players try in place of a missing image file
+const IMAGE_EXTENSIONS: &[&str] = &["bmp", "png", "jpg", "jpeg", "gif"];
+
+/// md5 and sha256 of a chart file, as lowercase hex strings
+#[derive(Debug, Clone)]
+pub struct ChartHash {
+    pub path: PathBuf,
+    pub md5: String,
+    pub sha256: String,
+}
+
+/// Compute md5 and sha256 of a file in a single pass.
+pub fn hash_file(path: &Path) -> Result<ChartHash> {
+    let mut file = fs::File::open(path)?;
+    let mut md5 = Md5::new();
+    let mut sha256 = Sha256::new();
+    let mut buf = [0u8; 64 * 1024];
+
+    loop {
+        let n = file.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        md5.update(&buf[..n]);
+        sha256.update(&buf[..n]);
+    }
+
+    Ok(ChartHash {
+        path: path.to_path_buf(),
+        md5: to_hex(&md5.finalize()),
+        sha256: to_hex(&sha256.finalize()),
+    })
+}
+
+/// Hash every BMS file under a directory.
+pub fn hash_charts(dir: &Path) -> Result<Vec<ChartHash>> {
+    normalize::find_bms_files(dir)?
+        .iter()
+        .map(|path| hash_file(path))
+        .collect()
+}
+
+/// Whether a table entry can be verified (it has at least one hash).
+pub fn has_hash(entry: &SongEntry) -> bool {
+    entry.md5.as_deref().is_some_and(|h| !h.is_empty())
+        || entry.sha256.as_deref().is_some_and(|h| !h.is_empty())
+}
+
+/// Find the chart matching a table entry's md5 or sha256.
+pub fn find_entry<'a>(hashes: &'a [ChartHash], entry: &SongEntry) -> Option<&'a ChartHash> {
+    let md5 = entry.md5.as_deref().map(str::to_lowercase);
+    let sha256 = entry.sha256.as_deref().map(str::to_lowercase);
+
+    hashes.iter().find(|h| {
+        md5.as_deref().is_some_and(|m| m == h.md5)
+            || sha256.as_deref().is_some_and(|s| s == h.sha256)
+    })
+}
+
+/// Resource files referenced by a chart that do not exist in its folder, allowing
+/// the extension substitutions BMS players make (e.g. `.wav` played as `.ogg`).
+pub fn missing_resources(chart: &Path) -> Result<Vec<String>> {
+    let folder = chart.parent().unwrap_or(Path::new("."));
+    let files = normalize::resource_set(folder)?;
+
+    let mut missing: Vec<String> = bms::parse(chart)?
+        .resources
+        .into_iter()
+        .filter(|r| !resource_exists(&files, r))
+        .map(|r| r.path)
+        .collect();
+    missing.sort();
+    missing.dedup();
+    Ok(missing)
+}
+
+/// Whether a resource is among `files` (lowercase relative paths, as built by
+/// `normalize::resource_set`), allowing the extension substitutions BMS players make.
+pub fn resource_exists(files: &HashSet<String>, resource: &bms::Resource) -> bool {
+    let path = resource.path.to_lowercase();
+    if files.contains(&path) {
+        return true;
+    }
+
+    let extensions = match resource.kind {
+        ResourceKind::Sound => SOUND_EXTENSIONS,
+        ResourceKind::Image => IMAGE_EXTENSIONS,
+    };
+    let stem = match path.rsplit_once('.') {
+        Some((stem, ext)) if extensions.contains(&ext) => stem,
+        _ => return false,
+    };
+    extensions
+        .iter()
+        .any(|ext| files.contains(&format!("{stem}.{ext}")))
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}